@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+
+/// Aliases defined with `alias`, shared between the shell and tab completion.
+#[derive(Debug, Default)]
+pub struct Aliases {
+    aliases: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    pub fn new() -> Self {
+        Aliases::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.aliases.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.aliases.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.aliases.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.aliases.clear();
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.aliases.iter()
+    }
+
+    /// Alias names starting with `prefix`, for tab completion.
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        self.aliases
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+// besides blanks: quoting and expansion characters, `/`, `=` and the characters that end a word
+const NOT_IN_ALIAS_NAMES: &str = "/$`='\"\\|&;()<>";
+
+pub fn is_valid_alias_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(|c| c.is_whitespace() || NOT_IN_ALIAS_NAMES.contains(c))
+}
+
+/// `name='value'` as `alias` prints it, quoted so it can be read back.
+pub fn alias_definition(name: &str, value: &str) -> String {
+    format!("alias {}='{}'", name, value.replace('\'', r"'\''"))
+}
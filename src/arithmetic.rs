@@ -0,0 +1,238 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Evaluates an integer expression as used by `declare -i`: numbers, variable names, `( )`,
+/// unary `- + !`, `* / %`, `+ -`, comparisons, `&&` and `||`. Unset or non-numeric variables
+/// count as 0.
+pub fn evaluate(expression: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<i64, String> {
+    let mut parser = Parser {
+        chars: expression.chars().peekable(),
+        lookup,
+    };
+    let value = parser.logical_or()?;
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+        None => Ok(value),
+        Some(_) => Err(syntax_error(expression, parser.chars.collect::<String>().as_str())),
+    }
+}
+
+fn syntax_error(expression: &str, token: &str) -> String {
+    format!(
+        "{}: syntax error in expression (error token is \"{}\")",
+        expression.trim(),
+        token
+    )
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    lookup: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    // consumes `operator` if it comes next
+    fn eat(&mut self, operator: &str) -> bool {
+        self.skip_whitespace();
+        let mut lookahead = self.chars.clone();
+        if operator.chars().all(|c| lookahead.next() == Some(c)) {
+            self.chars = lookahead;
+            return true;
+        }
+        false
+    }
+
+    fn logical_or(&mut self) -> Result<i64, String> {
+        let mut value = self.logical_and()?;
+        while self.eat("||") {
+            let rhs = self.logical_and()?;
+            value = (value != 0 || rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn logical_and(&mut self) -> Result<i64, String> {
+        let mut value = self.comparison()?;
+        while self.eat("&&") {
+            let rhs = self.comparison()?;
+            value = (value != 0 && rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn comparison(&mut self) -> Result<i64, String> {
+        let mut value = self.additive()?;
+        loop {
+            let compare: fn(i64, i64) -> bool = if self.eat("==") {
+                |a, b| a == b
+            } else if self.eat("!=") {
+                |a, b| a != b
+            } else if self.eat("<=") {
+                |a, b| a <= b
+            } else if self.eat(">=") {
+                |a, b| a >= b
+            } else if self.eat("<") {
+                |a, b| a < b
+            } else if self.eat(">") {
+                |a, b| a > b
+            } else {
+                return Ok(value);
+            };
+            let rhs = self.additive()?;
+            value = compare(value, rhs) as i64;
+        }
+    }
+
+    fn additive(&mut self) -> Result<i64, String> {
+        let mut value = self.multiplicative()?;
+        loop {
+            if self.eat("+") {
+                value = value.wrapping_add(self.multiplicative()?);
+            } else if self.eat("-") {
+                value = value.wrapping_sub(self.multiplicative()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<i64, String> {
+        let mut value = self.unary()?;
+        loop {
+            let operator = if self.eat("*") {
+                '*'
+            } else if self.eat("/") {
+                '/'
+            } else if self.eat("%") {
+                '%'
+            } else {
+                return Ok(value);
+            };
+            let rhs = self.unary()?;
+            value = match operator {
+                '*' => value.wrapping_mul(rhs),
+                _ if rhs == 0 => return Err("division by 0".to_string()),
+                '/' => value.wrapping_div(rhs),
+                _ => value.wrapping_rem(rhs),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat("-") {
+            return Ok(self.unary()?.wrapping_neg());
+        }
+        if self.eat("+") {
+            return self.unary();
+        }
+        if self.eat("!") {
+            return Ok((self.unary()? == 0) as i64);
+        }
+        self.operand()
+    }
+
+    fn operand(&mut self) -> Result<i64, String> {
+        self.skip_whitespace();
+        if self.eat("(") {
+            let value = self.logical_or()?;
+            if !self.eat(")") {
+                return Err("missing `)'".to_string());
+            }
+            return Ok(value);
+        }
+        let mut word = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            word.push(c);
+        }
+        match word.chars().next() {
+            None => Err("syntax error: operand expected".to_string()),
+            Some(c) if c.is_ascii_digit() => parse_number(&word),
+            Some(_) => {
+                let value = (self.lookup)(&word).unwrap_or_default();
+                Ok(value.trim().parse().unwrap_or(0))
+            }
+        }
+    }
+}
+
+// decimal, `0x` hexadecimal and leading-zero octal
+fn parse_number(word: &str) -> Result<i64, String> {
+    let parsed = if let Some(hex) = word.strip_prefix("0x").or(word.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if word.len() > 1 && word.starts_with('0') {
+        i64::from_str_radix(&word[1..], 8)
+    } else {
+        word.parse()
+    };
+    parsed.map_err(|_| format!("{}: value too great for base", word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Result<i64, String> {
+        let lookup = |name: &str| match name {
+            "x" => Some("5".to_string()),
+            "text" => Some("abc".to_string()),
+            _ => None,
+        };
+        evaluate(expression, &lookup)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("2 + 3 * 4"), Ok(14));
+        assert_eq!(eval("(2 + 3) * 4"), Ok(20));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("2 * 3 % 4"), Ok(2));
+        assert_eq!(eval("1 + 2 == 3"), Ok(1));
+        assert_eq!(eval("1 < 2 && 2 > 3 || 1"), Ok(1));
+        assert_eq!(eval("0 || 1 && 0"), Ok(0));
+        assert_eq!(eval("!0 + 1"), Ok(2));
+        assert_eq!(eval("- -3"), Ok(3));
+        assert_eq!(eval("-2 * -3"), Ok(6));
+    }
+
+    #[test]
+    fn division_truncates_towards_zero() {
+        assert_eq!(eval("7 / 2"), Ok(3));
+        assert_eq!(eval("-7 / 2"), Ok(-3));
+        assert_eq!(eval("-7 % 3"), Ok(-1));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(eval("3 <= 3"), Ok(1));
+        assert_eq!(eval("3 >= 4"), Ok(0));
+        assert_eq!(eval("3 != 4"), Ok(1));
+        assert_eq!(eval("3 < 3"), Ok(0));
+    }
+
+    #[test]
+    fn numbers_and_variables() {
+        assert_eq!(eval("0x1f"), Ok(31));
+        assert_eq!(eval("010"), Ok(8));
+        assert_eq!(eval("x * 2"), Ok(10));
+        assert_eq!(eval("unset + 1"), Ok(1));
+        assert_eq!(eval("text"), Ok(0));
+        assert_eq!(eval("  x  "), Ok(5));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval("1 / 0"), Err("division by 0".to_string()));
+        assert_eq!(eval("1 % 0"), Err("division by 0".to_string()));
+        assert_eq!(eval("1 +"), Err("syntax error: operand expected".to_string()));
+        assert_eq!(eval("(1 + 2"), Err("missing `)'".to_string()));
+        assert_eq!(eval("08"), Err("08: value too great for base".to_string()));
+        assert_eq!(
+            eval("1 2"),
+            Err("1 2: syntax error in expression (error token is \"2\")".to_string())
+        );
+    }
+}
@@ -1,88 +1,528 @@
-use std::{env, io::Write, path::PathBuf};
-use std::fs::{File, OpenOptions};
-use std::io::Read;
+use std::{env, io::Write, path::{Component, Path, PathBuf}};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::fd::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{self, Command};
+use std::time::{Duration, Instant};
+use is_executable::is_executable;
 use rustyline::history::History;
 use crate::{Shell, ShellAction};
+use crate::aliases::{alias_definition, is_valid_alias_name};
+use crate::conditional;
+use crate::history_expansion;
+use crate::printf::{self, interpret_escapes};
+use crate::command_hash::{search_path, DEFAULT_PATH};
+use crate::execute::{is_binary_file, is_script_without_interpreter, spawn_error_status, Input};
+use crate::redirection::install_child_fds;
+use crate::shell::{Jump, SET_OPTIONS, SHELL_OPTIONS};
+use crate::syntax::RESERVED_WORDS;
+use crate::signals::{self, Disposition};
+use crate::parser::Word;
+use crate::variables::{is_valid_name, split_subscript, AssignmentValue, Value, Variable, Variables};
 
-use crate::utils::write_to_dest;
+use crate::utils::{io_error_message, parse_flags, write_to_dest};
 
-pub fn exit() -> ShellAction {
+/// `exit [n]` leaves the shell with status `n`, or the status of the last command.
+pub fn exit(shell: &mut Shell, args: &[String], error: &mut dyn Write) -> ShellAction {
+    let status = match args {
+        [] => shell.last_status,
+        [status] => match status.trim().parse::<i64>() {
+            Ok(status) => status.rem_euclid(256) as i32,
+            Err(_) => {
+                write_to_dest(error, &format!("exit: {}: numeric argument required", status));
+                2
+            }
+        },
+        _ => {
+            write_to_dest(error, "exit: too many arguments");
+            return ShellAction::Status(1);
+        }
+    };
+    shell.last_status = status;
     ShellAction::Exit
 }
 
-pub fn print_current_dir(shell: &mut Shell, dest: &mut dyn Write) -> ShellAction {
-    write_to_dest(dest, format!("{}", shell.current_dir.display()).as_str());
+/// `break [n]` and `continue [n]` leave the innermost `n` loops, or go on with the next
+/// iteration of the `n`th one.
+pub fn loop_control(shell: &mut Shell, name: &str, args: &[String], error: &mut dyn Write) -> ShellAction {
+    if shell.loop_depth == 0 {
+        write_to_dest(error, &format!("{}: only meaningful in a `for', `while', or `until' loop", name));
+        return ShellAction::Continue;
+    }
+    let (count, status) = match args {
+        [] => (1, 0),
+        [count] => match count.trim().parse::<i64>() {
+            Ok(n) if n > 0 => (usize::try_from(n).unwrap_or(usize::MAX), 0),
+            Ok(_) => {
+                write_to_dest(error, &format!("{}: {}: loop count out of range", name, count));
+                (1, 1)
+            }
+            // bash treats this as a fatal error
+            Err(_) => {
+                write_to_dest(error, &format!("{}: {}: numeric argument required", name, count));
+                shell.last_status = 128;
+                return ShellAction::Exit;
+            }
+        },
+        _ => {
+            write_to_dest(error, &format!("{}: too many arguments", name));
+            return ShellAction::Status(1);
+        }
+    };
+    let count = count.min(shell.loop_depth);
+    shell.jump = Some(match name {
+        "break" => Jump::Break(count),
+        _ => Jump::Continue(count),
+    });
+    ShellAction::from_status(status)
+}
+
+/// `return [n]` leaves the running function with status `n`, or that of the last command.
+pub fn return_command(shell: &mut Shell, args: &[String], error: &mut dyn Write) -> ShellAction {
+    if shell.call_stack.is_empty() {
+        write_to_dest(error, "return: can only `return' from a function or sourced script");
+        return ShellAction::Status(2);
+    }
+    let status = match args {
+        [] => shell.last_status,
+        [status] => match status.trim().parse::<i64>() {
+            Ok(status) => status.rem_euclid(256) as i32,
+            Err(_) => {
+                write_to_dest(error, &format!("return: {}: numeric argument required", status));
+                2
+            }
+        },
+        _ => {
+            write_to_dest(error, "return: too many arguments");
+            return ShellAction::Status(1);
+        }
+    };
+    shell.jump = Some(Jump::Return);
+    ShellAction::from_status(status)
+}
+
+/// `local [-aAilrux] name[=value] ...` declares variables that the running function shadows,
+/// restored when it returns. It takes the options of `declare`.
+pub fn local(shell: &mut Shell, words: &[Word], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let Some(frame) = shell.call_stack.last_mut() else {
+        write_to_dest(error, "local: can only be used in a function");
+        return ShellAction::Status(1);
+    };
+    let names = words.iter().skip_while(|word| word.text.len() > 1 && word.text.starts_with(['-', '+']));
+    for word in names {
+        let target = word.assignment().map_or(word.text.as_str(), |(target, _)| target);
+        let name = target.strip_suffix('+').unwrap_or(target);
+        let variable = shell.variables.get_variable(name);
+        if !is_valid_name(name)
+            || variable.is_some_and(|variable| variable.attributes.readonly)
+            || frame.locals.iter().any(|(local, _)| local == name)
+        {
+            continue;
+        }
+        frame.locals.push((name.to_string(), variable.cloned()));
+        // `local name` starts out unset, and `local name+=value` appends to the outer value
+        if !target.ends_with('+') {
+            shell.variables.restore(name, None);
+        }
+    }
+    declare_command(shell, "local", words, output, error)
+}
+
+/// `pwd [-LP]`: the logical directory, as reached through symlinks, or with `-P` the
+/// physical one.
+pub fn print_current_dir(shell: &mut Shell, args: &[String], dest: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let physical = match parse_physical_flag(args) {
+        Ok((physical, _)) => physical,
+        Err(e) => {
+            write_to_dest(error, &format!("pwd: {}", e));
+            write_to_dest(error, "pwd: usage: pwd [-LP]");
+            return ShellAction::Status(2);
+        }
+    };
+    let dir = match physical {
+        true => match shell.current_dir.canonicalize() {
+            Ok(dir) => dir,
+            Err(e) => {
+                let message = io_error_message(&e);
+                write_to_dest(error, &format!("pwd: error retrieving current directory: {}", message));
+                return ShellAction::Status(1);
+            }
+        },
+        false => shell.current_dir.clone(),
+    };
+    write_to_dest(dest, format!("{}", dir.display()).as_str());
     ShellAction::Continue
 }
-pub fn set_current_dir(shell: &mut Shell, path: &PathBuf, dest_err: &mut dyn Write) {
-    match env::set_current_dir(path) {
-        Ok(_) => shell.current_dir = env::current_dir().unwrap(),
-        Err(_) => {
-            write_to_dest(
-                dest_err,
-                format!("cd: {}: No such file or directory", path.display()).as_str(),
-            );
+
+// the `-L`/`-P` options of `cd` and `pwd`, where the last one given wins
+fn parse_physical_flag(args: &[String]) -> Result<(bool, &[String]), String> {
+    let (flags, rest) = parse_flags(args, "LP")?;
+    let physical = flags.last().is_some_and(|(flag, _)| *flag == 'P');
+    Ok((physical, rest))
+}
+
+/// Changes the working directory to `path`, relative to the logical current directory.
+/// `..` is resolved lexically unless `physical`, which resolves symlinks instead. Keeps
+/// `$PWD` and `$OLDPWD` up to date.
+pub fn set_current_dir(shell: &mut Shell, path: &Path, physical: bool) -> io::Result<()> {
+    let logical = normalize_path(&shell.current_dir.join(path));
+    let dir = match physical {
+        true => {
+            env::set_current_dir(shell.current_dir.join(path))?;
+            env::current_dir()?
         }
+        // a lexical `..` can name a directory that doesn't exist, such as after following a
+        // symlink into a different tree; the physical path is tried before giving up
+        false => match env::set_current_dir(&logical) {
+            Ok(()) => logical,
+            Err(e) => {
+                env::set_current_dir(path).map_err(|_| e)?;
+                env::current_dir()?
+            }
+        },
     };
+    shell.frecency.record(&dir);
+    let previous = std::mem::replace(&mut shell.current_dir, dir);
+    let current = shell.current_dir.display().to_string();
+    for (name, value) in [("OLDPWD", previous.display().to_string()), ("PWD", current)] {
+        let _ = shell.variables.set(name, value);
+        shell.variables.attributes_mut(name).exported = true;
+    }
+    Ok(())
+}
+
+/// `path` with `.` and `..` components removed without looking at the filesystem.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
 }
+
+/// `cd [-L|-P] [dir]`: with no `dir` goes to `$HOME`, with `-` to `$OLDPWD`, and searches
+/// `$CDPATH` for relative names. Prints the new directory when it was found through `-` or
+/// `$CDPATH`.
 pub fn change_directories(
     shell: &mut Shell,
-    path: &str,
-    dest_out: Option<&mut dyn Write>,
+    args: &[String],
+    dest_out: &mut dyn Write,
     dest_err: &mut dyn Write,
 ) -> ShellAction {
-    if path == "~" {
-        let home = env::home_dir();
-        return match home {
-            Some(p) => {
-                set_current_dir(shell, &p, dest_err);
-                ShellAction::Continue
+    let (physical, args) = match parse_physical_flag(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(dest_err, &format!("cd: {}", e));
+            write_to_dest(dest_err, "cd: usage: cd [-L|-P] [dir]");
+            return ShellAction::Status(2);
+        }
+    };
+    if args.len() > 1 {
+        write_to_dest(dest_err, "cd: too many arguments");
+        return ShellAction::Status(1);
+    }
+    let (dir, print) = match args.first().map(String::as_str) {
+        None | Some("~") => match shell.get_var("HOME").map(PathBuf::from).or_else(env::home_dir) {
+            Some(home) => (home, false),
+            None => {
+                write_to_dest(dest_err, "cd: HOME not set");
+                return ShellAction::Status(1);
             }
+        },
+        Some("-") => match shell.get_var("OLDPWD") {
+            Some(previous) => (PathBuf::from(previous), true),
             None => {
-                ShellAction::Continue
+                write_to_dest(dest_err, "cd: OLDPWD not set");
+                return ShellAction::Status(1);
             }
-        }
+        },
+        Some(dir) => match search_cdpath(shell, dir) {
+            Some(found) => (found, true),
+            None => (PathBuf::from(dir), false),
+        },
     };
-    set_current_dir(shell, &PathBuf::from(&path), dest_err);
-    return ShellAction::Continue;
+    let previous = shell.current_dir.clone();
+    if let Err(e) = set_current_dir(shell, &dir, physical) {
+        write_to_dest(dest_err, &format!("cd: {}: {}", dir.display(), io_error_message(&e)));
+        return ShellAction::Status(1);
+    }
+    if shell.options.contains("autopushd") {
+        shell.dir_stack.insert(0, previous);
+    }
+    if print {
+        write_to_dest(dest_out, &shell.current_dir.display().to_string());
+    }
+    ShellAction::Continue
 }
-pub fn echo(args: &[String], dest: &mut dyn Write) -> ShellAction {
-    let content = args.join(" ");
-
-    write_to_dest(dest, &content);
 
+// `$CDPATH` applies to names that don't start with `/`, `.` or `..`. A directory found
+// through an empty entry, which means the current directory, isn't reported.
+fn search_cdpath(shell: &Shell, dir: &str) -> Option<PathBuf> {
+    let first = dir.split('/').next().unwrap_or_default();
+    if matches!(first, "" | "." | "..") {
+        return None;
+    }
+    let cdpath = shell.get_var("CDPATH")?;
+    for entry in cdpath.split(':') {
+        let base = match entry {
+            "" => shell.current_dir.clone(),
+            entry => shell.current_dir.join(entry),
+        };
+        if base.join(dir).is_dir() {
+            return (!entry.is_empty()).then(|| base.join(dir));
+        }
+    }
+    None
+}
+/// `echo [-neE] args`: `-n` drops the newline and `-e` interprets backslash escapes, which
+/// `-E` turns back off. Only arguments made entirely of these letters count as options.
+pub fn echo(args: &[String], dest: &mut dyn Write) -> ShellAction {
+    let is_option = |arg: &&String| {
+        arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| "neE".contains(c))
+    };
+    let options = args.iter().take_while(is_option).count();
+    let (mut newline, mut escapes) = (true, false);
+    for c in args[..options].iter().flat_map(|arg| arg[1..].chars()) {
+        match c {
+            'n' => newline = false,
+            'e' => escapes = true,
+            _ => escapes = false,
+        }
+    }
+    let mut content = args[options..].join(" ");
+    if escapes {
+        let (text, complete) = interpret_escapes(&content, false);
+        content = text;
+        newline &= complete;
+    }
+    if newline {
+        content.push('\n');
+    }
+    let _ = dest.write_all(content.as_bytes()).and_then(|_| dest.flush());
     ShellAction::Continue
 }
 
-pub fn type_command(shell: &Shell, args: &Vec<String>, dest: &mut dyn Write) -> ShellAction {
-    if args.is_empty() {
-        return ShellAction::Continue;
+/// `printf [-v var] format [arguments]`, writing to `var` instead of the output with `-v`.
+pub fn printf(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let (flags, args) = match parse_flags(args, "v:") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("printf: {}", e));
+            write_to_dest(error, "printf: usage: printf [-v var] format [arguments]");
+            return ShellAction::Status(2);
+        }
+    };
+    let Some((format, args)) = args.split_first() else {
+        write_to_dest(error, "printf: usage: printf [-v var] format [arguments]");
+        return ShellAction::Status(2);
+    };
+    let variable = flags.into_iter().find_map(|(_, name)| name);
+    if let Some(name) = &variable
+        && !is_valid_name(split_subscript(name).0)
+    {
+        write_to_dest(error, &format!("printf: `{}': not a valid identifier", name));
+        return ShellAction::Status(2);
     }
 
-    let cmd_name = &args[0];
-
-    if shell.builtins.contains(cmd_name) {
-        write_to_dest(dest, format!("{} is a shell builtin", cmd_name).as_str());
-        return ShellAction::Continue;
+    let (text, errors) = printf::printf(format, args);
+    for message in &errors {
+        write_to_dest(error, &format!("printf: {}", message));
+    }
+    match variable {
+        Some(name) => {
+            if let Err(e) = shell.variables.assign(&name, &AssignmentValue::Scalar(text)) {
+                write_to_dest(error, &format!("printf: {}", e));
+                return ShellAction::Status(1);
+            }
+        }
+        None => {
+            let _ = output.write_all(text.as_bytes()).and_then(|_| output.flush());
+        }
     }
+    ShellAction::from_status(!errors.is_empty() as i32)
+}
 
-    if let Some(exe) = shell.executables.iter().find(|e| e.name == *cmd_name) {
-        write_to_dest(
-            dest,
-            format!("{} is {}", cmd_name, exe.path.display()).as_str(),
-        );
-        return ShellAction::Continue;
+/// What a command name refers to, in the order the shell looks.
+enum CommandKind {
+    Alias(String),
+    Keyword,
+    // a shell function, with its definition
+    Function(String),
+    Builtin,
+    File(PathBuf),
+}
+
+// the meanings of `name`: only the one that would run, or with `all` every one including
+// each match on PATH. `path_only` skips aliases, keywords and builtins, `skip_functions`
+// skips functions.
+fn command_kinds(
+    shell: &Shell,
+    name: &str,
+    all: bool,
+    path_only: bool,
+    skip_functions: bool,
+) -> Vec<CommandKind> {
+    let mut kinds = Vec::new();
+    if !path_only {
+        if let Some(value) = shell.aliases.borrow().get(name) {
+            kinds.push(CommandKind::Alias(value.clone()));
+        }
+        if name == "[[" || name == "]]" || RESERVED_WORDS.contains(&name) {
+            kinds.push(CommandKind::Keyword);
+        }
+        if let Some(function) = shell.functions.get(name).filter(|_| !skip_functions) {
+            kinds.push(CommandKind::Function(function.text.clone()));
+        }
+        if shell.builtins.iter().any(|builtin| builtin == name) {
+            kinds.push(CommandKind::Builtin);
+        }
+    }
+    if !all && !kinds.is_empty() {
+        kinds.truncate(1);
+        return kinds;
     }
+    if name.contains('/') {
+        if is_executable(name) && !Path::new(name).is_dir() {
+            kinds.push(CommandKind::File(PathBuf::from(name)));
+        }
+    } else if all {
+        shell.sync_command_path();
+        let paths = shell.commands.borrow().search_all(name);
+        kinds.extend(paths.into_iter().map(CommandKind::File));
+    } else if let Some(path) = shell.lookup_command(name) {
+        kinds.push(CommandKind::File(path));
+    }
+    kinds
+}
 
-    write_to_dest(dest, format!("{}: not found", cmd_name).as_str());
+// `type` and `command -V` output for one meaning of `name`
+fn describe_command(name: &str, kind: &CommandKind) -> String {
+    match kind {
+        CommandKind::Alias(value) => format!("{} is aliased to `{}'", name, value),
+        CommandKind::Keyword => format!("{} is a shell keyword", name),
+        CommandKind::Function(text) => format!("{} is a function\n{}", name, text),
+        CommandKind::Builtin => format!("{} is a shell builtin", name),
+        CommandKind::File(path) => format!("{} is {}", name, path.display()),
+    }
+}
 
-    ShellAction::Continue
+/// `type [-afptP] name ...`: `-a` shows every meaning, `-t` a single word (`alias`,
+/// `keyword`, `function`, `builtin` or `file`), `-p` the file that would run and `-P`
+/// searches PATH even for aliases and builtins. `-f` leaves functions out.
+pub fn type_command(shell: &Shell, args: &[String], dest: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let (flags, names) = match parse_flags(args, "afptP") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("type: {}", e));
+            write_to_dest(error, "type: usage: type [-afptP] name [name ...]");
+            return ShellAction::Status(2);
+        }
+    };
+    let has_flag = |c| flags.iter().any(|(flag, _)| *flag == c);
+    let mut status = 0;
+    for name in names {
+        let kinds = command_kinds(shell, name, has_flag('a'), has_flag('P'), has_flag('f'));
+        if kinds.is_empty() {
+            if !has_flag('t') && !has_flag('p') && !has_flag('P') {
+                write_to_dest(dest, format!("{}: not found", name).as_str());
+            }
+            status = 1;
+            continue;
+        }
+        for kind in &kinds {
+            let line = match kind {
+                _ if has_flag('t') => match kind {
+                    CommandKind::Alias(_) => "alias".to_string(),
+                    CommandKind::Keyword => "keyword".to_string(),
+                    CommandKind::Function(_) => "function".to_string(),
+                    CommandKind::Builtin => "builtin".to_string(),
+                    CommandKind::File(_) => "file".to_string(),
+                },
+                CommandKind::File(path) if has_flag('p') || has_flag('P') => path.display().to_string(),
+                _ if has_flag('p') => continue,
+                kind => describe_command(name, kind),
+            };
+            write_to_dest(dest, &line);
+        }
+    }
+    ShellAction::from_status(status)
 }
-pub fn history(shell: &mut Shell, args: &Vec<String>, output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+/// `history [n]` lists the history, the last `n` entries with a count. `-c` clears it,
+/// `-d offset` or `-d start-end` deletes entries (negative offsets count back from the end),
+/// `-s args` adds an entry without running it, `-p args` prints the args after history
+/// expansion, and `-r`, `-w`, `-a` and `-n` read, write, append to and read new lines from
+/// a file.
+pub fn history(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
 
-    let first_arg = args.get(0).map(|s| s.as_str()).unwrap_or("0");
+    let first_arg = args.first().map(|s| s.as_str()).unwrap_or("0");
     let second_arg = args.get(1).map(|s| s.as_str()).unwrap_or("");
     match first_arg {
+        "-c" => {
+            let _ = shell.read_line.clear_history();
+            shell.last_written_index = 0;
+            return ShellAction::Continue;
+        }
+        "-d" => return delete_history(shell, second_arg, error),
+        "-s" if args.len() > 1 => {
+            // the `history -s` line itself is replaced by its arguments
+            let mut entries = shell.history_entries();
+            if shell.history_line_added {
+                entries.pop();
+                shell.history_line_added = false;
+            }
+            entries.push(args[1..].join(" "));
+            shell.replace_history(entries);
+            return ShellAction::Continue;
+        }
+        "-p" => {
+            let mut entries = shell.history_entries();
+            if shell.history_line_added {
+                entries.pop();
+                shell.history_line_added = false;
+            }
+            let mut status = 0;
+            for arg in &args[1..] {
+                match history_expansion::expand(arg, &entries) {
+                    Ok(expansion) => write_to_dest(output, &expansion.line),
+                    Err(_) => {
+                        write_to_dest(error, &format!("history: {}: history expansion failed", arg));
+                        status = 1;
+                    }
+                }
+            }
+            shell.replace_history(entries);
+            return ShellAction::from_status(status);
+        }
+        "-n" => {
+            let path = match second_arg {
+                "" => shell.history_file.clone(),
+                file => PathBuf::from(file),
+            };
+            let contents = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    write_to_dest(error, &format!("history: {}: {}", path.display(), io_error_message(&e)));
+                    return ShellAction::Status(1);
+                }
+            };
+            let lines: Vec<&str> = contents.lines().collect();
+            let new_lines = lines.get(shell.history_file_lines..).unwrap_or_default();
+            let mut entries = shell.history_entries();
+            entries.extend(new_lines.iter().filter(|line| !line.is_empty()).map(|line| line.to_string()));
+            shell.replace_history(entries);
+            // lines from the file don't need appending to it again
+            shell.last_written_index += new_lines.len();
+            shell.history_file_lines = lines.len();
+            return ShellAction::Continue;
+        }
         "-r" => {
             if second_arg.is_empty() {
                 write_to_dest(error, "history: missing file operand");
@@ -92,6 +532,9 @@ pub fn history(shell: &mut Shell, args: &Vec<String>, output: &mut dyn Write, er
             if let Err(e) = shell.read_line.load_history(&path) {
                 write_to_dest(error, &format!("history: {}", e));
             }
+            if path == shell.history_file {
+                shell.history_file_lines = fs::read_to_string(&path).map(|c| c.lines().count()).unwrap_or(0);
+            }
             return ShellAction::Continue;
         }
         "-w" => {
@@ -110,6 +553,9 @@ pub fn history(shell: &mut Shell, args: &Vec<String>, output: &mut dyn Write, er
                             return ShellAction::Continue;
                         }
                     }
+                    if path == shell.history_file {
+                        shell.history_file_lines = shell.read_line.history().len();
+                    }
                 }
                 Err(e) => {
                     write_to_dest(error, &format!("history: {}", e));
@@ -140,6 +586,9 @@ pub fn history(shell: &mut Shell, args: &Vec<String>, output: &mut dyn Write, er
                         }
                     }
 
+                    if path == shell.history_file {
+                        shell.history_file_lines += history.len().saturating_sub(shell.last_written_index);
+                    }
                     shell.last_written_index = history.len();
                 }
                 Err(e) => {
@@ -152,7 +601,22 @@ pub fn history(shell: &mut Shell, args: &Vec<String>, output: &mut dyn Write, er
 
         _ => (),
     }
-    let n = first_arg.parse().unwrap_or(0);
+    if args.len() > 1 {
+        write_to_dest(error, "history: too many arguments");
+        return ShellAction::Status(1);
+    }
+    let n = match first_arg.parse::<usize>() {
+        Ok(n) => n,
+        Err(_) if first_arg.starts_with('-') => {
+            write_to_dest(error, &format!("history: {}: invalid option", first_arg));
+            write_to_dest(error, "history: usage: history [-c] [-d offset] [n] or history -anrw [filename] or history -ps arg [arg...]");
+            return ShellAction::Status(2);
+        }
+        Err(_) => {
+            write_to_dest(error, &format!("history: {}: numeric argument required", first_arg));
+            return ShellAction::Status(1);
+        }
+    };
     let history = shell.read_line.history();
     let len = history.len();
     let start = match n {
@@ -169,3 +633,1326 @@ pub fn history(shell: &mut Shell, args: &Vec<String>, output: &mut dyn Write, er
 
 }
 
+// `history -d`: one offset or a `start-end` range, 1-based, where negative offsets count
+// back from the end
+fn delete_history(shell: &mut Shell, arg: &str, error: &mut dyn Write) -> ShellAction {
+    let len = shell.read_line.history().len();
+    let resolve = |offset: &str| -> Option<usize> {
+        let offset: i64 = offset.parse().ok()?;
+        let index = match offset {
+            offset if offset < 0 => len as i64 + offset,
+            offset => offset - 1,
+        };
+        usize::try_from(index).ok().filter(|index| *index < len)
+    };
+    let range = match arg.parse::<i64>() {
+        Ok(_) => resolve(arg).map(|index| (index, index)),
+        // `start-end`, where either side may be negative: `-5--2`
+        Err(_) => arg
+            .char_indices()
+            .skip(1)
+            .filter(|(_, c)| *c == '-')
+            .find_map(|(i, _)| Some((resolve(&arg[..i])?, resolve(&arg[i + 1..])?))),
+    };
+    let Some((start, end)) = range.filter(|(start, end)| start <= end) else {
+        match arg {
+            "" => write_to_dest(error, "history: -d: option requires an argument"),
+            arg => write_to_dest(error, &format!("history: {}: history position out of range", arg)),
+        }
+        return ShellAction::Status(1);
+    };
+    let entries: Vec<String> = shell
+        .read_line
+        .history()
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i < start || *i > end)
+        .map(|(_, entry)| entry.clone())
+        .collect();
+    shell.replace_history(entries);
+    // deleted entries that were already in the file no longer count as written
+    let written_deleted = shell.last_written_index.min(end + 1).saturating_sub(start);
+    shell.last_written_index -= written_deleted;
+    ShellAction::Continue
+}
+
+pub fn trap(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let mut args = args;
+    match args.first().map(|s| s.as_str()) {
+        Some("-l") => {
+            let names: Vec<String> = signals::signals()
+                .iter()
+                .map(|(name, number)| format!("{:>2}) SIG{}", number, name))
+                .collect();
+            for row in names.chunks(5) {
+                write_to_dest(output, &row.join("\t"));
+            }
+            return ShellAction::Continue;
+        }
+        Some("-p") => {
+            args = &args[1..];
+            return print_traps(shell, args, output, error);
+        }
+        Some("--") => args = &args[1..],
+        _ => (),
+    }
+    if args.is_empty() {
+        return print_traps(shell, args, output, error);
+    }
+
+    // a lone condition, or a leading signal number, resets instead of setting a handler
+    let (action, conditions) = if args.len() == 1 || args[0].parse::<u32>().is_ok() {
+        ("-", args)
+    } else {
+        (args[0].as_str(), &args[1..])
+    };
+
+    let mut status = 0;
+    for condition in conditions {
+        let Some(name) = signals::condition_name(condition) else {
+            write_to_dest(error, &format!("trap: {}: invalid signal specification", condition));
+            status = 1;
+            continue;
+        };
+        let disposition = match action {
+            "-" => {
+                shell.traps.remove(name);
+                match name {
+                    "HUP" | "TERM" => Disposition::Catch,
+                    _ => Disposition::Default,
+                }
+            }
+            "" => {
+                shell.traps.insert(name.to_string(), String::new());
+                Disposition::Ignore
+            }
+            action => {
+                shell.traps.insert(name.to_string(), action.to_string());
+                Disposition::Catch
+            }
+        };
+        if let Some(number) = signals::signal_number(name) {
+            signals::set_disposition(number, disposition);
+        }
+    }
+
+    ShellAction::from_status(status)
+}
+
+fn print_traps(shell: &Shell, conditions: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let mut names = Vec::new();
+    let mut status = 0;
+    if conditions.is_empty() {
+        names.extend(signals::conditions());
+    }
+    for condition in conditions {
+        match signals::condition_name(condition) {
+            Some(name) => names.push(name),
+            None => {
+                write_to_dest(error, &format!("trap: {}: invalid signal specification", condition));
+                status = 1;
+            }
+        }
+    }
+
+    for name in names {
+        if let Some(action) = shell.traps.get(name) {
+            let label = match signals::signal_number(name) {
+                Some(_) => format!("SIG{}", name),
+                None => name.to_string(),
+            };
+            write_to_dest(
+                output,
+                &format!("trap -- '{}' {}", action.replace('\'', "'\\''"), label),
+            );
+        }
+    }
+
+    ShellAction::from_status(status)
+}
+
+pub fn read(shell: &mut Shell, args: &[String], input: &mut dyn Input, error: &mut dyn Write) -> ShellAction {
+    let (flags, names) = match parse_flags(args, "rsa:d:n:p:t:u:") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("read: {}", e));
+            return ShellAction::Status(2);
+        }
+    };
+    let mut raw = false;
+    let mut silent = false;
+    let mut array = None;
+    let mut delimiter = b'\n';
+    let mut nchars = None;
+    let mut prompt = None;
+    let mut timeout = None;
+    let mut fd = None;
+    for (flag, value) in flags {
+        let value = value.unwrap_or_default();
+        match flag {
+            'r' => raw = true,
+            's' => silent = true,
+            'a' => array = Some(value),
+            'd' => delimiter = value.bytes().next().unwrap_or(0),
+            'p' => prompt = Some(value),
+            'n' => match value.parse::<usize>() {
+                Ok(n) => nchars = Some(n),
+                Err(_) => {
+                    write_to_dest(error, &format!("read: {}: invalid number", value));
+                    return ShellAction::Status(1);
+                }
+            },
+            't' => match value.parse::<f64>() {
+                Ok(t) if t >= 0.0 => timeout = Some(Duration::from_secs_f64(t)),
+                _ => {
+                    write_to_dest(error, &format!("read: {}: invalid timeout specification", value));
+                    return ShellAction::Status(1);
+                }
+            },
+            _ => match value.parse::<i32>() {
+                Ok(n) if n >= 0 => fd = Some(n),
+                _ => {
+                    write_to_dest(error, &format!("read: {}: invalid file descriptor specification", value));
+                    return ShellAction::Status(1);
+                }
+            },
+        }
+    }
+    if let Some(name) = array.iter().chain(names).find(|n| !is_valid_name(n)) {
+        write_to_dest(error, &format!("read: `{}': not a valid identifier", name));
+        return ShellAction::Status(1);
+    }
+
+    let mut fd_input;
+    let source: &mut dyn Input = match fd {
+        Some(fd) => match shell.fds.reader(fd) {
+            Ok(file) => {
+                fd_input = file;
+                &mut fd_input
+            }
+            Err(e) => {
+                write_to_dest(error, &format!("read: {}: invalid file descriptor: {}", fd, io_error_message(&e)));
+                return ShellAction::Status(1);
+            }
+        },
+        None => input,
+    };
+    let source_fd = source.as_raw_fd();
+    let is_tty = unsafe { libc::isatty(source_fd) } == 1;
+
+    if timeout == Some(Duration::ZERO) {
+        return ShellAction::from_status(if poll_readable(source_fd, Duration::ZERO) { 0 } else { 1 });
+    }
+    if let Some(prompt) = &prompt
+        && is_tty
+    {
+        let _ = write!(error, "{}", prompt);
+        let _ = error.flush();
+    }
+    let saved_termios = if silent && is_tty { disable_echo(source_fd) } else { None };
+
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut bytes = Vec::new();
+    let mut chars = 0;
+    let mut pending = 0;
+    let mut escaped = false;
+    let mut status = 0;
+    while nchars != Some(chars) {
+        if let Some(deadline) = deadline
+            && !poll_readable(source_fd, deadline.saturating_duration_since(Instant::now()))
+        {
+            status = 142;
+            break;
+        }
+        let mut byte = [0u8];
+        match source.read(&mut byte) {
+            Ok(0) => {
+                status = 1;
+                break;
+            }
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                write_to_dest(error, &format!("read: read error: {}", e));
+                status = 1;
+                break;
+            }
+        }
+        let b = byte[0];
+        if escaped {
+            escaped = false;
+            // backslash-newline continues the line
+            if b == b'\n' {
+                continue;
+            }
+        } else if !raw && b == b'\\' {
+            escaped = true;
+            continue;
+        } else if b == delimiter {
+            break;
+        }
+        bytes.push(b);
+        pending = match pending {
+            0 => utf8_len(b) - 1,
+            n => n - 1,
+        };
+        if pending == 0 {
+            chars += 1;
+        }
+    }
+
+    if let Some(termios) = saved_termios {
+        unsafe { libc::tcsetattr(source_fd, libc::TCSANOW, &termios) };
+    }
+
+    let line = String::from_utf8_lossy(&bytes).to_string();
+    let ifs = shell.get_var("IFS").unwrap_or_else(|| " \t\n".to_string());
+    let assigned = if let Some(array) = array {
+        let fields = split_fields(&line, &ifs, usize::MAX);
+        shell.variables.set_array(&array, fields.into_iter().enumerate().collect())
+    } else if names.is_empty() {
+        shell.variables.set("REPLY", line)
+    } else {
+        let mut fields = split_fields(&line, &ifs, names.len()).into_iter();
+        names
+            .iter()
+            .try_for_each(|name| shell.variables.set(name, fields.next().unwrap_or_default()))
+    };
+    if let Err(e) = assigned {
+        write_to_dest(error, &format!("read: {}", e));
+        return ShellAction::Status(1);
+    }
+
+    ShellAction::from_status(status)
+}
+
+pub fn mapfile(shell: &mut Shell, args: &[String], input: &mut dyn Input, error: &mut dyn Write) -> ShellAction {
+    let (flags, names) = match parse_flags(args, "td:n:O:s:u:") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("mapfile: {}", e));
+            return ShellAction::Status(2);
+        }
+    };
+    let mut strip = false;
+    let mut delimiter = b'\n';
+    let mut count = 0;
+    let mut origin = None;
+    let mut skip = 0;
+    let mut fd = None;
+    for (flag, value) in flags {
+        let value = value.unwrap_or_default();
+        if flag == 't' {
+            strip = true;
+            continue;
+        }
+        if flag == 'd' {
+            delimiter = value.bytes().next().unwrap_or(0);
+            continue;
+        }
+        let Ok(number) = value.parse::<usize>() else {
+            write_to_dest(error, &format!("mapfile: {}: invalid number", value));
+            return ShellAction::Status(1);
+        };
+        match flag {
+            'n' => count = number,
+            'O' => origin = Some(number),
+            's' => skip = number,
+            _ => fd = Some(number as i32),
+        }
+    }
+    let name = names.first().map(|s| s.as_str()).unwrap_or("MAPFILE");
+    if !is_valid_name(name) {
+        write_to_dest(error, &format!("mapfile: `{}': not a valid identifier", name));
+        return ShellAction::Status(1);
+    }
+
+    let mut fd_input;
+    let source: &mut dyn Input = match fd {
+        Some(fd) => match shell.fds.reader(fd) {
+            Ok(file) => {
+                fd_input = file;
+                &mut fd_input
+            }
+            Err(e) => {
+                write_to_dest(error, &format!("mapfile: {}: invalid file descriptor: {}", fd, io_error_message(&e)));
+                return ShellAction::Status(1);
+            }
+        },
+        None => input,
+    };
+
+    let mut values = match origin {
+        Some(_) => shell.variables.get_array(name),
+        None => BTreeMap::new(),
+    };
+    let mut index = origin.unwrap_or(0);
+    let mut read = 0;
+    while count == 0 || read < count {
+        let mut record = Vec::new();
+        let found = match read_record(source, delimiter, &mut record) {
+            Ok(found) => found,
+            Err(e) => {
+                write_to_dest(error, &format!("mapfile: read error: {}", e));
+                return ShellAction::Status(1);
+            }
+        };
+        if record.is_empty() && !found {
+            break;
+        }
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        if found && !strip {
+            record.push(delimiter);
+        }
+        values.insert(index, String::from_utf8_lossy(&record).to_string());
+        index += 1;
+        read += 1;
+    }
+    if let Err(e) = shell.variables.set_array(name, values) {
+        write_to_dest(error, &format!("mapfile: {}", e));
+        return ShellAction::Status(1);
+    }
+
+    ShellAction::Continue
+}
+
+pub fn shopt(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let (flags, names) = match parse_flags(args, "supq") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("shopt: {}", e));
+            return ShellAction::Status(2);
+        }
+    };
+    let has_flag = |c| flags.iter().any(|(flag, _)| *flag == c);
+    if let Some(name) = names.iter().find(|n| !SHELL_OPTIONS.contains(&n.as_str())) {
+        write_to_dest(error, &format!("shopt: {}: invalid shell option name", name));
+        return ShellAction::Status(1);
+    }
+
+    if has_flag('s') || has_flag('u') {
+        for name in names {
+            if has_flag('s') {
+                shell.options.insert(name.clone());
+            } else {
+                shell.options.remove(name);
+            }
+        }
+        return ShellAction::Continue;
+    }
+
+    let names: Vec<&str> = match names.is_empty() {
+        true => SHELL_OPTIONS.to_vec(),
+        false => names.iter().map(|s| s.as_str()).collect(),
+    };
+    let mut status = 0;
+    for name in names {
+        let enabled = shell.options.contains(name);
+        if !enabled {
+            status = 1;
+        }
+        if has_flag('q') {
+            continue;
+        }
+        let line = match (has_flag('p'), enabled) {
+            (true, true) => format!("shopt -s {}", name),
+            (true, false) => format!("shopt -u {}", name),
+            (false, true) => format!("{:<15}\ton", name),
+            (false, false) => format!("{:<15}\toff", name),
+        };
+        write_to_dest(output, &line);
+    }
+
+    ShellAction::from_status(status)
+}
+
+pub fn hash(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let (flags, names) = match parse_flags(args, "rdtlp:") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("hash: {}", e));
+            return ShellAction::Status(2);
+        }
+    };
+    let has_flag = |c| flags.iter().any(|(flag, _)| *flag == c);
+    shell.sync_command_path();
+    let mut commands = shell.commands.borrow_mut();
+
+    if has_flag('r') {
+        commands.clear();
+    }
+    if let Some((_, Some(path))) = flags.iter().find(|(flag, _)| *flag == 'p') {
+        for name in names {
+            commands.insert(name, PathBuf::from(path));
+        }
+        return ShellAction::Continue;
+    }
+
+    let mut status = 0;
+    if has_flag('d') || has_flag('t') {
+        for name in names {
+            let found = match has_flag('d') {
+                true => commands.remove(name),
+                false => match commands.get(name) {
+                    Some(command) if names.len() > 1 => {
+                        write_to_dest(output, &format!("{}\t{}", name, command.path.display()));
+                        true
+                    }
+                    Some(command) => {
+                        write_to_dest(output, &format!("{}", command.path.display()));
+                        true
+                    }
+                    None => false,
+                },
+            };
+            if !found {
+                write_to_dest(error, &format!("hash: {}: not found", name));
+                status = 1;
+            }
+        }
+        return ShellAction::from_status(status);
+    }
+
+    if names.is_empty() {
+        if has_flag('r') {
+            return ShellAction::Continue;
+        }
+        if commands.entries().next().is_none() {
+            write_to_dest(output, "hash: hash table empty");
+            return ShellAction::Continue;
+        }
+        if !has_flag('l') {
+            write_to_dest(output, "hits\tcommand");
+        }
+        for (name, command) in commands.entries() {
+            let line = match has_flag('l') {
+                true => format!("builtin hash -p {} {}", command.path.display(), name),
+                false => format!("{:>4}\t{}", command.hits, command.path.display()),
+            };
+            write_to_dest(output, &line);
+        }
+        return ShellAction::Continue;
+    }
+
+    for name in names {
+        if shell.builtins.contains(name) {
+            continue;
+        }
+        match commands.search(name) {
+            Some(path) => commands.insert(name, path),
+            None => {
+                write_to_dest(error, &format!("hash: {}: not found", name));
+                status = 1;
+            }
+        }
+    }
+    ShellAction::from_status(status)
+}
+
+pub fn set(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next_if(|arg| arg.len() > 1 && arg.starts_with(['-', '+'])) {
+        if arg == "--" {
+            shell.positional.truncate(1);
+            break;
+        }
+        let enable = arg.starts_with('-');
+        for flag in arg[1..].chars() {
+            let name = match flag {
+                'o' => match args.next() {
+                    Some(name) if SET_OPTIONS.iter().any(|(option, _)| option == name) => name.as_str(),
+                    Some(name) => {
+                        write_to_dest(error, &format!("set: {}: invalid option name", name));
+                        return ShellAction::Status(1);
+                    }
+                    None => {
+                        print_set_options(shell, !enable, output);
+                        continue;
+                    }
+                },
+                _ => match SET_OPTIONS.iter().find(|(_, letter)| *letter == Some(flag)) {
+                    Some((name, _)) => name,
+                    None => {
+                        write_to_dest(error, &format!("set: {}{}: invalid option", &arg[..1], flag));
+                        return ShellAction::Status(2);
+                    }
+                },
+            };
+            if enable {
+                shell.options.insert(name.to_string());
+            } else {
+                shell.options.remove(name);
+            }
+        }
+    }
+    // the remaining words replace the positional parameters
+    if args.peek().is_some() {
+        shell.positional.truncate(1);
+        shell.positional.extend(args.cloned());
+    }
+    ShellAction::Continue
+}
+
+// `set -o` prints a table, `set +o` the commands that recreate the current settings
+fn print_set_options(shell: &Shell, as_commands: bool, output: &mut dyn Write) {
+    for (name, _) in SET_OPTIONS {
+        let enabled = shell.options.contains(name);
+        let line = match (as_commands, enabled) {
+            (true, true) => format!("set -o {}", name),
+            (true, false) => format!("set +o {}", name),
+            (false, true) => format!("{:<15}\ton", name),
+            (false, false) => format!("{:<15}\toff", name),
+        };
+        write_to_dest(output, &line);
+    }
+}
+
+/// `NAME=value` words on their own assign shell variables.
+pub fn assign(shell: &mut Shell, assignments: &[(String, AssignmentValue)], error: &mut dyn Write) -> ShellAction {
+    for (target, value) in assignments {
+        if let Err(e) = shell.variables.assign(target, value) {
+            write_to_dest(error, &format!("bash: {}", e));
+            return ShellAction::Status(1);
+        }
+    }
+    ShellAction::Continue
+}
+
+pub fn export(shell: &mut Shell, words: &[Word], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let args: Vec<String> = words.iter().map(|word| word.text.clone()).collect();
+    let (flags, names) = match parse_flags(&args, "fnp") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("export: {}", e));
+            return ShellAction::Status(2);
+        }
+    };
+    if names.is_empty() {
+        print_declarations(shell, output, |variable| variable.attributes.exported);
+        return ShellAction::Continue;
+    }
+    let names = &words[args.len() - names.len()..];
+    match flags.iter().any(|(flag, _)| *flag == 'n') {
+        true => declare_variables(shell, "export", names, "", "x", error),
+        false => declare_variables(shell, "export", names, "x", "", error),
+    }
+}
+
+pub fn readonly(shell: &mut Shell, words: &[Word], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let args: Vec<String> = words.iter().map(|word| word.text.clone()).collect();
+    let (flags, names) = match parse_flags(&args, "aAfp") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("readonly: {}", e));
+            return ShellAction::Status(2);
+        }
+    };
+    if names.is_empty() {
+        print_declarations(shell, output, |variable| variable.attributes.readonly);
+        return ShellAction::Continue;
+    }
+    let mut attributes: String = flags.iter().map(|(flag, _)| *flag).filter(|f| "aA".contains(*f)).collect();
+    attributes.push('r');
+    let names = &words[args.len() - names.len()..];
+    declare_variables(shell, "readonly", names, &attributes, "", error)
+}
+
+pub fn unset(shell: &mut Shell, args: &[String], error: &mut dyn Write) -> ShellAction {
+    let (flags, names) = match parse_flags(args, "fvn") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("unset: {}", e));
+            return ShellAction::Status(2);
+        }
+    };
+    if flags.iter().any(|(flag, _)| *flag == 'f') {
+        for name in names {
+            shell.functions.remove(name);
+        }
+        return ShellAction::Continue;
+    }
+    let mut status = 0;
+    for word in names {
+        let unset = match split_subscript(word) {
+            (name, _) if !is_valid_name(name) => {
+                write_to_dest(error, &format!("unset: `{}': not a valid identifier", word));
+                status = 1;
+                continue;
+            }
+            (name, Some(subscript)) => shell.variables.unset_element(name, subscript),
+            (name, None) => shell.variables.unset(name),
+        };
+        if let Err(e) = unset {
+            write_to_dest(error, &format!("unset: {}", e));
+            status = 1;
+        }
+    }
+    ShellAction::from_status(status)
+}
+
+/// `declare`/`typeset`: `-x -r -i -l -u -a -A` set attributes, `+` removes them and `-p`
+/// prints declarations.
+pub fn declare(shell: &mut Shell, words: &[Word], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    declare_command(shell, "declare", words, output, error)
+}
+
+fn declare_command(
+    shell: &mut Shell,
+    builtin: &str,
+    words: &[Word],
+    output: &mut dyn Write,
+    error: &mut dyn Write,
+) -> ShellAction {
+    let mut set = String::new();
+    let mut unset = String::new();
+    let mut index = 0;
+    while let Some(arg) = words.get(index).map(|w| &w.text).filter(|a| a.len() > 1 && a.starts_with(['-', '+'])) {
+        index += 1;
+        if arg == "--" {
+            break;
+        }
+        for flag in arg[1..].chars() {
+            if !"aAfgilprux".contains(flag) {
+                write_to_dest(error, &format!("{}: {}{}: invalid option", builtin, &arg[..1], flag));
+                return ShellAction::Status(2);
+            }
+            match arg.starts_with('-') {
+                true => set.push(flag),
+                false => unset.push(flag),
+            }
+        }
+    }
+    let names = &words[index..];
+    if set.contains('f') {
+        return print_functions(shell, builtin, names, output, error);
+    }
+
+    if names.is_empty() {
+        let filter: String = set.chars().filter(|f| *f != 'p' && *f != 'g').collect();
+        print_declarations(shell, output, |variable| {
+            filter.chars().all(|flag| has_attribute(variable, flag))
+        });
+        return ShellAction::Continue;
+    }
+    if set.contains('p') {
+        let mut status = 0;
+        for name in names {
+            match shell.variables.declaration(&name.text) {
+                Some(declaration) => write_to_dest(output, &declaration),
+                None => {
+                    write_to_dest(error, &format!("{}: {}: not found", builtin, name.text));
+                    status = 1;
+                }
+            }
+        }
+        return ShellAction::from_status(status);
+    }
+    declare_variables(shell, builtin, names, &set, &unset, error)
+}
+
+// `declare -f`: the definitions of the functions in `names`, or of every function
+fn print_functions(
+    shell: &Shell,
+    builtin: &str,
+    names: &[Word],
+    output: &mut dyn Write,
+    error: &mut dyn Write,
+) -> ShellAction {
+    if names.is_empty() {
+        let mut functions: Vec<_> = shell.functions.values().collect();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        for function in functions {
+            write_to_dest(output, &function.text);
+        }
+        return ShellAction::Continue;
+    }
+    let mut status = 0;
+    for name in names {
+        match shell.functions.get(&name.text) {
+            Some(function) => write_to_dest(output, &function.text),
+            None => {
+                write_to_dest(error, &format!("{}: {}: not found", builtin, name.text));
+                status = 1;
+            }
+        }
+    }
+    ShellAction::from_status(status)
+}
+
+fn has_attribute(variable: &Variable, flag: char) -> bool {
+    let attributes = &variable.attributes;
+    match flag {
+        'a' => matches!(variable.value, Some(Value::Indexed(_))),
+        'A' => matches!(variable.value, Some(Value::Associative(_))),
+        'i' => attributes.integer,
+        'l' => attributes.lowercase,
+        'r' => attributes.readonly,
+        'u' => attributes.uppercase,
+        'x' => attributes.exported,
+        _ => true,
+    }
+}
+
+fn print_declarations(shell: &Shell, output: &mut dyn Write, filter: impl Fn(&Variable) -> bool) {
+    for name in shell.variables.names() {
+        if shell.variables.get_variable(name).is_some_and(&filter)
+            && let Some(declaration) = shell.variables.declaration(name)
+        {
+            write_to_dest(output, &declaration);
+        }
+    }
+}
+
+// Applies `NAME` / `NAME=value` words for declare, export and readonly. `set` and `unset` hold
+// the attribute letters given with `-` and `+`.
+fn declare_variables(
+    shell: &mut Shell,
+    builtin: &str,
+    words: &[Word],
+    set: &str,
+    unset: &str,
+    error: &mut dyn Write,
+) -> ShellAction {
+    let mut status = 0;
+    for word in words {
+        let (target, value) = match word.assignment() {
+            Some((target, value)) => (target, Some(value)),
+            None => (word.text.as_str(), None),
+        };
+        let name = target.strip_suffix('+').unwrap_or(target);
+        if !is_valid_name(name) {
+            write_to_dest(error, &format!("{}: `{}': not a valid identifier", builtin, word.text));
+            status = 1;
+            continue;
+        }
+        if let Err(e) = declare_variable(&mut shell.variables, target, value.as_ref(), set, unset) {
+            write_to_dest(error, &format!("{}: {}", builtin, e));
+            status = 1;
+        }
+    }
+    ShellAction::from_status(status)
+}
+
+fn declare_variable(
+    variables: &mut Variables,
+    target: &str,
+    value: Option<&AssignmentValue>,
+    set: &str,
+    unset: &str,
+) -> Result<(), String> {
+    let name = target.strip_suffix('+').unwrap_or(target);
+    let readonly = variables.get_variable(name).is_some_and(|v| v.attributes.readonly);
+    if readonly && (value.is_some() || unset.contains('r')) {
+        return Err(format!("{}: readonly variable", name));
+    }
+    if set.contains('a') || set.contains('A') {
+        variables.declare_array(name, set.contains('A'))?;
+    }
+
+    let attributes = variables.attributes_mut(name);
+    for flag in set.chars() {
+        match flag {
+            'x' => attributes.exported = true,
+            'i' => attributes.integer = true,
+            'l' => (attributes.lowercase, attributes.uppercase) = (true, false),
+            'u' => (attributes.lowercase, attributes.uppercase) = (false, true),
+            _ => (),
+        }
+    }
+    for flag in unset.chars() {
+        match flag {
+            'x' => attributes.exported = false,
+            'i' => attributes.integer = false,
+            'l' => attributes.lowercase = false,
+            'u' => attributes.uppercase = false,
+            _ => (),
+        }
+    }
+
+    if let Some(value) = value {
+        variables.assign(target, value)?;
+    }
+    if set.contains('r') {
+        variables.attributes_mut(name).readonly = true;
+    }
+    Ok(())
+}
+
+/// `command` running a name is resolved when the command line is parsed; this handles `-v`,
+/// which prints how each name would be run, `-V`, which describes it like `type`, and the
+/// invocations without a name.
+pub fn command(shell: &Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let (flags, names) = match parse_flags(args, "pvV") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("command: {}", e));
+            write_to_dest(error, "command: usage: command [-pVv] command [arg ...]");
+            return ShellAction::Status(2);
+        }
+    };
+    let has_flag = |c| flags.iter().any(|(flag, _)| *flag == c);
+    if !has_flag('v') && !has_flag('V') {
+        return ShellAction::Continue;
+    }
+    let mut status = 0;
+    for name in names {
+        let kind = match command_kinds(shell, name, false, false, false).into_iter().next() {
+            Some(CommandKind::File(_)) | None if has_flag('p') && !name.contains('/') => {
+                search_path(DEFAULT_PATH, name).into_iter().next().map(CommandKind::File)
+            }
+            kind => kind,
+        };
+        let line = match (kind, has_flag('V')) {
+            (Some(kind), true) => describe_command(name, &kind),
+            (Some(CommandKind::Alias(value)), false) => alias_definition(name, &value),
+            (Some(CommandKind::File(path)), false) => path.display().to_string(),
+            (Some(_), false) => name.clone(),
+            (None, verbose) => {
+                if verbose {
+                    write_to_dest(error, &format!("command: {}: not found", name));
+                }
+                status = 1;
+                continue;
+            }
+        };
+        write_to_dest(output, &line);
+    }
+    ShellAction::from_status(status)
+}
+
+/// `builtin name args` runs a builtin directly, which is resolved when the command line is
+/// parsed; this only sees names that aren't builtins.
+pub fn builtin_command(args: &[String], error: &mut dyn Write) -> ShellAction {
+    match args.first() {
+        Some(name) => {
+            write_to_dest(error, &format!("builtin: {}: not a shell builtin", name));
+            ShellAction::Status(1)
+        }
+        None => ShellAction::Continue,
+    }
+}
+
+/// Replaces the shell with `args[0]`. Without a command the redirections are made permanent
+/// by the caller instead.
+pub fn exec(shell: &mut Shell, args: &[String], error: &mut dyn Write) -> ShellAction {
+    let (flags, args) = match parse_flags(args, "cla:") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("exec: {}", e));
+            return ShellAction::Status(2);
+        }
+    };
+    let Some((name, args)) = args.split_first() else {
+        return ShellAction::Continue;
+    };
+
+    let path = match name.contains('/') {
+        true => Some(PathBuf::from(name)),
+        false => shell.find_command(name),
+    };
+    let Some(path) = path else {
+        write_to_dest(error, &format!("exec: {}: not found", name));
+        return ShellAction::Status(127);
+    };
+
+    let mut argv0 = name.clone();
+    let mut command = Command::new(&path);
+    command.args(args).env_clear().envs(shell.variables.exported());
+    for (flag, value) in flags {
+        match (flag, value) {
+            ('a', Some(value)) => argv0 = value,
+            ('l', _) => argv0 = format!("-{}", argv0),
+            ('c', _) => {
+                command.env_clear();
+            }
+            _ => (),
+        }
+    }
+    command.arg0(argv0);
+
+    let child_fds = shell.fds.child_fds();
+    unsafe {
+        command.pre_exec(move || install_child_fds(&child_fds));
+    }
+    let _ = io::stdout().flush();
+    // only returns on failure
+    let e = match is_script_without_interpreter(&path) {
+        true => io::Error::from_raw_os_error(libc::ENOEXEC),
+        false => command.exec(),
+    };
+    if e.raw_os_error() == Some(libc::ENOEXEC) && !is_binary_file(&path) {
+        // the script takes over this shell, as the program would have
+        let status = shell.run_script_file(&path, name, args);
+        let _ = io::stdout().flush();
+        process::exit(status);
+    }
+    match spawn_error_status(&e) {
+        127 => write_to_dest(error, &format!("exec: {}: not found", name)),
+        _ => write_to_dest(error, &format!("exec: {}: {}", name, io_error_message(&e))),
+    }
+    ShellAction::Status(spawn_error_status(&e))
+}
+
+fn read_record(source: &mut dyn Input, delimiter: u8, record: &mut Vec<u8>) -> io::Result<bool> {
+    let mut byte = [0u8];
+    loop {
+        match source.read(&mut byte) {
+            Ok(0) => return Ok(false),
+            Ok(_) if byte[0] == delimiter => return Ok(true),
+            Ok(_) => record.push(byte[0]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Splits `line` into at most `max` fields on `ifs`; the last field takes the rest of the line.
+fn split_fields(line: &str, ifs: &str, max: usize) -> Vec<String> {
+    let is_ifs_space = |c: char| ifs.contains(c) && c.is_whitespace();
+    let mut fields = Vec::new();
+    let mut rest = line.trim_start_matches(is_ifs_space);
+    while !rest.is_empty() {
+        if fields.len() + 1 == max {
+            fields.push(rest.trim_end_matches(is_ifs_space).to_string());
+            break;
+        }
+        let Some(end) = rest.find(|c| ifs.contains(c)) else {
+            fields.push(rest.to_string());
+            break;
+        };
+        fields.push(rest[..end].to_string());
+        rest = rest[end..].trim_start_matches(is_ifs_space);
+        if let Some(c) = rest.chars().next().filter(|c| ifs.contains(*c)) {
+            rest = rest[c.len_utf8()..].trim_start_matches(is_ifs_space);
+        }
+    }
+    fields
+}
+
+fn utf8_len(first_byte: u8) -> usize {
+    match first_byte {
+        b if b >= 0xF0 => 4,
+        b if b >= 0xE0 => 3,
+        b if b >= 0xC0 => 2,
+        _ => 1,
+    }
+}
+
+fn poll_readable(fd: RawFd, timeout: Duration) -> bool {
+    let mut poll_fd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+    unsafe { libc::poll(&mut poll_fd, 1, timeout) > 0 }
+}
+
+fn disable_echo(fd: RawFd) -> Option<libc::termios> {
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return None;
+        }
+        let saved = termios;
+        termios.c_lflag &= !libc::ECHO;
+        libc::tcsetattr(fd, libc::TCSANOW, &termios);
+        Some(saved)
+    }
+}
+
+/// `alias [-p] [name[=value] ...]`: defines aliases, or prints them in a reusable form.
+pub fn alias(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let (_, args) = match parse_flags(args, "p") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("alias: {}", e));
+            write_to_dest(error, "alias: usage: alias [-p] [name[=value] ... ]");
+            return ShellAction::Status(2);
+        }
+    };
+    let mut aliases = shell.aliases.borrow_mut();
+    if args.is_empty() {
+        for (name, value) in aliases.entries() {
+            write_to_dest(output, &alias_definition(name, value));
+        }
+        return ShellAction::Continue;
+    }
+    let mut status = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, _)) if !is_valid_alias_name(name) => {
+                write_to_dest(error, &format!("alias: `{}': invalid alias name", name));
+                status = 1;
+            }
+            Some((name, value)) => aliases.set(name, value),
+            None => match aliases.get(arg) {
+                Some(value) => write_to_dest(output, &alias_definition(arg, value)),
+                None => {
+                    write_to_dest(error, &format!("alias: {}: not found", arg));
+                    status = 1;
+                }
+            },
+        }
+    }
+    ShellAction::from_status(status)
+}
+
+/// `unalias [-a] name ...`.
+pub fn unalias(shell: &mut Shell, args: &[String], error: &mut dyn Write) -> ShellAction {
+    let (flags, names) = match parse_flags(args, "a") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("unalias: {}", e));
+            write_to_dest(error, "unalias: usage: unalias [-a] name [name ...]");
+            return ShellAction::Status(2);
+        }
+    };
+    let mut aliases = shell.aliases.borrow_mut();
+    if !flags.is_empty() {
+        aliases.clear();
+        return ShellAction::Continue;
+    }
+    if names.is_empty() {
+        write_to_dest(error, "unalias: usage: unalias [-a] name [name ...]");
+        return ShellAction::Status(2);
+    }
+    let mut status = 0;
+    for name in names {
+        if !aliases.remove(name) {
+            write_to_dest(error, &format!("unalias: {}: not found", name));
+            status = 1;
+        }
+    }
+    ShellAction::from_status(status)
+}
+
+/// `dirs [-clpv] [+N|-N]`: shows the directory stack, with `$HOME` as `~` unless `-l`.
+pub fn dirs(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let mut flags = String::new();
+    let mut position = None;
+    for arg in args {
+        if arg.len() > 1 && arg.starts_with(['+', '-']) && arg[1..].parse::<usize>().is_ok() {
+            position = Some(arg);
+        } else if let Some(letters) = arg.strip_prefix('-').filter(|l| !l.is_empty()) {
+            if let Some(c) = letters.chars().find(|c| !"clpv".contains(*c)) {
+                write_to_dest(error, &format!("dirs: -{}: invalid option", c));
+                write_to_dest(error, "dirs: usage: dirs [-clpv] [+N] [-N]");
+                return ShellAction::Status(2);
+            }
+            flags.push_str(letters);
+        } else {
+            write_to_dest(error, &format!("dirs: {}: invalid argument", arg));
+            return ShellAction::Status(1);
+        }
+    }
+    if flags.contains('c') {
+        shell.dir_stack.clear();
+        return ShellAction::Continue;
+    }
+    let home = shell.get_var("HOME");
+    let show = |dir: &PathBuf| match flags.contains('l') {
+        true => dir.display().to_string(),
+        false => abbreviate_home(dir, home.as_deref()),
+    };
+    let directories = shell.directories();
+    if let Some(position) = position {
+        match shell.dir_stack_index(position) {
+            Some(index) => write_to_dest(output, &show(&directories[index])),
+            None => {
+                write_to_dest(error, &format!("dirs: {}: directory stack index out of range", position));
+                return ShellAction::Status(1);
+            }
+        }
+        return ShellAction::Continue;
+    }
+    if flags.contains('v') {
+        for (index, dir) in directories.iter().enumerate() {
+            write_to_dest(output, &format!("{:2}  {}", index, show(dir)));
+        }
+    } else if flags.contains('p') {
+        for dir in &directories {
+            write_to_dest(output, &show(dir));
+        }
+    } else {
+        let line: Vec<String> = directories.iter().map(show).collect();
+        write_to_dest(output, &line.join(" "));
+    }
+    ShellAction::Continue
+}
+
+// `dir` with a leading `$HOME` written as `~`
+fn abbreviate_home(dir: &Path, home: Option<&str>) -> String {
+    match home.filter(|home| !home.is_empty()).and_then(|home| dir.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => dir.display().to_string(),
+    }
+}
+
+/// `pushd [dir|+N|-N]`: with `dir`, changes to it and pushes the old directory; with `+N`
+/// or `-N`, rotates that entry to the top; with nothing, swaps the top two entries.
+pub fn pushd(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let args = args.strip_prefix(&["--".to_string()]).unwrap_or(args);
+    if args.len() > 1 {
+        write_to_dest(error, "pushd: too many arguments");
+        return ShellAction::Status(1);
+    }
+    let mut directories = shell.directories();
+    let target = match args.first() {
+        None if shell.dir_stack.is_empty() => {
+            write_to_dest(error, "pushd: no other directory");
+            return ShellAction::Status(1);
+        }
+        None => {
+            directories.swap(0, 1);
+            directories
+        }
+        Some(arg) if arg.starts_with(['+', '-']) && arg.len() > 1 => match shell.dir_stack_index(arg) {
+            Some(index) => {
+                directories.rotate_left(index);
+                directories
+            }
+            None if arg[1..].parse::<usize>().is_ok() => {
+                write_to_dest(error, &format!("pushd: {}: directory stack index out of range", arg));
+                return ShellAction::Status(1);
+            }
+            None => {
+                let option: String = arg.chars().take(2).collect();
+                write_to_dest(error, &format!("pushd: {}: invalid option", option));
+                write_to_dest(error, "pushd: usage: pushd [-n] [+N | -N | dir]");
+                return ShellAction::Status(2);
+            }
+        },
+        Some(dir) => [PathBuf::from(dir)].into_iter().chain(directories).collect(),
+    };
+    if let Err(e) = set_current_dir(shell, &target[0], false) {
+        write_to_dest(error, &format!("pushd: {}: {}", target[0].display(), io_error_message(&e)));
+        return ShellAction::Status(1);
+    }
+    shell.dir_stack = target[1..].to_vec();
+    dirs(shell, &[], output, error)
+}
+
+/// `popd [+N|-N]`: drops the top entry and changes to the new top, or drops entry `N`.
+pub fn popd(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let args = args.strip_prefix(&["--".to_string()]).unwrap_or(args);
+    if args.len() > 1 {
+        write_to_dest(error, "popd: too many arguments");
+        return ShellAction::Status(1);
+    }
+    if shell.dir_stack.is_empty() {
+        write_to_dest(error, "popd: directory stack empty");
+        return ShellAction::Status(1);
+    }
+    let index = match args.first() {
+        None => 0,
+        Some(arg) => match shell.dir_stack_index(arg).filter(|_| arg.starts_with(['+', '-'])) {
+            Some(index) => index,
+            None if arg.strip_prefix(['+', '-']).is_some_and(|n| n.parse::<usize>().is_ok()) => {
+                write_to_dest(error, &format!("popd: {}: directory stack index out of range", arg));
+                return ShellAction::Status(1);
+            }
+            None => {
+                write_to_dest(error, &format!("popd: {}: invalid argument", arg));
+                write_to_dest(error, "popd: usage: popd [-n] [+N | -N]");
+                return ShellAction::Status(2);
+            }
+        },
+    };
+    if index == 0 {
+        let top = shell.dir_stack[0].clone();
+        if let Err(e) = set_current_dir(shell, &top, false) {
+            write_to_dest(error, &format!("popd: {}: {}", top.display(), io_error_message(&e)));
+            return ShellAction::Status(1);
+        }
+    }
+    shell.dir_stack.remove(index.max(1) - 1);
+    dirs(shell, &[], output, error)
+}
+
+/// `z [-l] [-x [dir]] fragment ...`: jumps to the most frecent visited directory whose path
+/// contains the fragments in order. `-l` lists the candidates instead, lowest score first,
+/// and `-x` forgets a directory, the current one by default.
+pub fn z(shell: &mut Shell, args: &[String], output: &mut dyn Write, error: &mut dyn Write) -> ShellAction {
+    let (flags, fragments) = match parse_flags(args, "lx") {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            write_to_dest(error, &format!("z: {}", e));
+            write_to_dest(error, "z: usage: z [-l] [-x [dir]] [fragment ...]");
+            return ShellAction::Status(2);
+        }
+    };
+    let has_flag = |c| flags.iter().any(|(flag, _)| *flag == c);
+    if has_flag('x') {
+        let dir = match fragments.first() {
+            Some(dir) => normalize_path(&shell.current_dir.join(dir)),
+            None => shell.current_dir.clone(),
+        };
+        if !shell.frecency.remove(&dir) {
+            write_to_dest(error, &format!("z: {}: not in the database", dir.display()));
+            return ShellAction::Status(1);
+        }
+        return ShellAction::Continue;
+    }
+
+    let matches = shell.frecency.matches(fragments);
+    if has_flag('l') || fragments.is_empty() {
+        for (score, dir) in matches.iter().rev() {
+            write_to_dest(output, &format!("{:<10.1} {}", score, dir.display()));
+        }
+        return ShellAction::from_status(matches.is_empty() as i32);
+    }
+    let Some((_, best)) = matches.into_iter().next() else {
+        write_to_dest(error, &format!("z: no match for {}", fragments.join(" ")));
+        return ShellAction::Status(1);
+    };
+    if let Err(e) = set_current_dir(shell, &best, false) {
+        write_to_dest(error, &format!("z: {}: {}", best.display(), io_error_message(&e)));
+        return ShellAction::Status(1);
+    }
+    ShellAction::Continue
+}
+
+/// `test expr` and `[ expr ]`: 0 when the expression is true, 1 when false, 2 on errors.
+pub fn test(shell: &mut Shell, name: &str, args: &[String], error: &mut dyn Write) -> ShellAction {
+    let args = match name {
+        "[" => match args.split_last() {
+            Some((last, args)) if last == "]" => args,
+            _ => {
+                write_to_dest(error, "[: missing `]'");
+                return ShellAction::Status(2);
+            }
+        },
+        _ => args,
+    };
+    match conditional::test(args, shell) {
+        Ok(result) => ShellAction::from_status(!result as i32),
+        Err(e) => {
+            write_to_dest(error, &format!("{}: {}", name, e));
+            ShellAction::Status(2)
+        }
+    }
+}
+
+/// `[[ expr ]]`. A `=~` match leaves the match and its groups in `BASH_REMATCH`.
+pub fn conditional(shell: &mut Shell, args: &[Word], error: &mut dyn Write) -> ShellAction {
+    let Some((_, args)) = args.split_last().filter(|(last, _)| last.text == "]]") else {
+        let message = "syntax error in conditional expression: unexpected end of line";
+        write_to_dest(error, &format!("bash: {}", message));
+        return ShellAction::Status(2);
+    };
+    match conditional::conditional(args, shell) {
+        Ok((result, rematch)) => {
+            if let Some(groups) = rematch {
+                let groups = groups.into_iter().enumerate().collect();
+                let _ = shell.variables.set_array("BASH_REMATCH", groups);
+            }
+            ShellAction::from_status(!result as i32)
+        }
+        Err(e) => {
+            write_to_dest(error, &format!("bash: {}", e));
+            ShellAction::Status(2)
+        }
+    }
+}
+
@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use is_executable::is_executable;
+
+/// The PATH used by `command -p`, which finds the standard utilities whatever PATH holds.
+pub const DEFAULT_PATH: &str = "/usr/bin:/bin:/usr/sbin:/sbin";
+
+#[derive(Debug)]
+pub struct HashedCommand {
+    pub path: PathBuf,
+    pub hits: usize,
+}
+
+/// Remembers where commands were found on PATH, like bash's `hash` table. Entries are dropped
+/// when PATH changes or when the file they point to is no longer executable.
+#[derive(Debug, Default)]
+pub struct CommandHash {
+    path_var: Option<String>,
+    commands: BTreeMap<String, HashedCommand>,
+    // executables in each PATH directory, for completion; refreshed when the directory changes
+    listings: HashMap<PathBuf, (Option<SystemTime>, Vec<String>)>,
+}
+
+impl CommandHash {
+    pub fn new() -> Self {
+        CommandHash::default()
+    }
+
+    pub fn set_path(&mut self, path_var: Option<String>) {
+        if self.path_var != path_var {
+            self.path_var = path_var;
+            self.clear();
+        }
+    }
+
+    /// Finds `name` on PATH, using the cached location while it is still valid.
+    pub fn find(&mut self, name: &str) -> Option<PathBuf> {
+        if let Some(command) = self.commands.get_mut(name)
+            && is_command(&command.path)
+        {
+            command.hits += 1;
+            return Some(command.path.clone());
+        }
+        self.commands.remove(name);
+
+        let path = self.search(name)?;
+        let command = HashedCommand { path: path.clone(), hits: 1 };
+        self.commands.insert(name.to_string(), command);
+        Some(path)
+    }
+
+    /// Where `name` would be run from, like [`CommandHash::find`] but without adding to the
+    /// cache or counting a hit.
+    pub fn lookup(&self, name: &str) -> Option<PathBuf> {
+        match self.commands.get(name) {
+            Some(command) if is_command(&command.path) => Some(command.path.clone()),
+            _ => self.search(name),
+        }
+    }
+
+    /// Searches PATH for `name` without touching the cache.
+    pub fn search(&self, name: &str) -> Option<PathBuf> {
+        self.search_all(name).into_iter().next()
+    }
+
+    /// Every match for `name` on PATH, in PATH order.
+    pub fn search_all(&self, name: &str) -> Vec<PathBuf> {
+        match &self.path_var {
+            Some(path_var) => search_path(path_var, name),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, path: PathBuf) {
+        self.commands
+            .insert(name.to_string(), HashedCommand { path, hits: 0 });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&HashedCommand> {
+        self.commands.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.commands.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+        self.listings.clear();
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &HashedCommand)> {
+        self.commands.iter()
+    }
+
+    /// Names of the commands on PATH starting with `prefix`, for tab completion.
+    pub fn complete(&mut self, prefix: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        for dir in self.directories() {
+            let modified = fs::metadata(&dir).and_then(|m| m.modified()).ok();
+            let stale = !matches!(self.listings.get(&dir), Some((cached, _)) if *cached == modified);
+            if stale {
+                self.listings.insert(dir.clone(), (modified, list_commands(&dir)));
+            }
+            if let Some((_, listing)) = self.listings.get(&dir) {
+                names.extend(listing.iter().filter(|name| name.starts_with(prefix)).cloned());
+            }
+        }
+        names
+    }
+
+    fn directories(&self) -> Vec<PathBuf> {
+        match &self.path_var {
+            Some(path_var) => env::split_paths(path_var).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Every executable called `name` in the directories of `path_var`.
+pub fn search_path(path_var: &str, name: &str) -> Vec<PathBuf> {
+    env::split_paths(path_var)
+        .map(|dir| dir.join(name))
+        .filter(|path| is_command(path))
+        .collect()
+}
+
+fn is_command(path: &Path) -> bool {
+    path.is_file() && is_executable(path)
+}
+
+fn list_commands(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| is_command(&entry.path()))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect()
+}
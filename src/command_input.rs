@@ -1,21 +1,61 @@
-use crate::parser::parse_input;
-use crate::{Shell, ShellAction};
+use crate::command_hash::{search_path, DEFAULT_PATH};
+use crate::parser::{parse_input, Word};
+use crate::Shell;
 
-use is_executable::is_executable;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
 use std::path::PathBuf;
+use std::rc::Rc;
 
-use crate::redirection::Redirection;
+use crate::redirection::{is_here_document_operator, read_here_documents, Redirection};
+use crate::syntax::{Command, CommandText, CompoundCommand, FunctionDefinition};
 use crate::utils::split_by_delimiter;
+use crate::variables::{split_assignment, AssignmentValue};
 
 #[derive(Debug)]
-
+#[allow(clippy::upper_case_acronyms)]
 pub enum BuiltInCommand {
-    Exit,
+    Exit(Vec<String>),
     Echo(Vec<String>),
     Type(Vec<String>),
     CD(Vec<String>),
-    PWD,
+    PWD(Vec<String>),
     History(Vec<String>),
+    Trap(Vec<String>),
+    Read(Vec<String>),
+    Mapfile(Vec<String>),
+    Shopt(Vec<String>),
+    Exec(Vec<String>),
+    Set(Vec<String>),
+    Hash(Vec<String>),
+    Command(Vec<String>),
+    Export(Vec<Word>),
+    Unset(Vec<String>),
+    Readonly(Vec<Word>),
+    Declare(Vec<Word>),
+    Alias(Vec<String>),
+    Unalias(Vec<String>),
+    Dirs(Vec<String>),
+    Pushd(Vec<String>),
+    Popd(Vec<String>),
+    Z(Vec<String>),
+    Printf(Vec<String>),
+    // `test` or `[`, by name
+    Test(String, Vec<String>),
+    Conditional(Vec<Word>),
+    Builtin(Vec<String>),
+    Return(Vec<String>),
+    Local(Vec<Word>),
+    // defines a shell function
+    Define(Rc<FunctionDefinition>),
+    // runs a shell function, by name
+    Call(String, Vec<String>),
+    // `break` or `continue`, by name
+    LoopControl(String, Vec<String>),
+    // `true` and `:`, or `false`, ignoring their arguments
+    Status(i32),
+    // a command made only of `NAME=value` words
+    Assign,
 }
 #[derive(Debug)]
 pub enum CommandType {
@@ -26,96 +66,350 @@ pub enum CommandType {
         args: Vec<String>,
     },
     Unknown(String),
+    /// A compound command such as a loop, run like a builtin with its own redirections.
+    Compound(CompoundCommand),
+    /// A stage of a longer pipeline, expanded only in the subshell that runs it, so that
+    /// `$BASHPID` or `${name:=word}` belong to the subshell.
+    Deferred(Command),
+}
+
+/// Why a line didn't produce a command.
+#[derive(Debug)]
+pub enum CmdError {
+    /// A syntax error, such as a redirection without a target.
+    Syntax(String),
+    /// An expansion that failed, such as `${name:?}` with `name` unset.
+    Expansion(String),
+}
+
+impl CmdError {
+    pub fn status(&self) -> i32 {
+        match self {
+            CmdError::Syntax(_) => 2,
+            CmdError::Expansion(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for CmdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CmdError::Syntax(message) => write!(f, "{}", message),
+            CmdError::Expansion(message) => write!(f, "bash: {}", message),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Cmd {
     pub command_type: CommandType,
-    pub command_str: String,
-    pub redirect_std_out: Option<Redirection>,
-    pub redirect_std_error: Option<Redirection>,
+    pub redirections: Vec<Redirection>,
+    /// `NAME=value` words before the command name, set for this command only.
+    pub assignments: Vec<(String, AssignmentValue)>,
     pub child: Option<Box<Cmd>>,
 }
 
 impl Cmd {
-    pub fn new(input: &str, shell: &Shell) -> Option<Self> {
-        let tokens = parse_input(&input);
-        let mut cmds_split_by_pipe = split_by_delimiter(tokens.clone(), "|".to_string());
+    pub fn new(command: &CommandText, shell: &mut Shell) -> Result<Option<Self>, CmdError> {
+        let line = &command.text;
+        let mut here_documents = read_here_documents(line, &command.here_documents, shell)
+            .map_err(CmdError::Expansion)?;
+
+        let tokens = parse_input(line, shell).map_err(CmdError::Expansion)?;
+        let tokens = Self::expand_aliases(tokens, shell).map_err(CmdError::Expansion)?;
+        let mut cmds_split_by_pipe = split_by_delimiter(tokens, Word::new("|"));
+        let mut here_documents_by_cmd: Vec<VecDeque<String>> = cmds_split_by_pipe
+            .iter()
+            .map(|tokens| {
+                let count = tokens.iter().filter(|t| is_here_document_operator(t)).count();
+                here_documents.drain(..count.min(here_documents.len())).collect()
+            })
+            .collect();
+
+        Self::build_piped_commands(&mut cmds_split_by_pipe, &mut here_documents_by_cmd, shell)
+    }
+
+    /// The expanded pipeline stages of `command`.
+    pub fn from_command(command: &Command, shell: &mut Shell) -> Result<Option<Self>, CmdError> {
+        match command {
+            Command::Simple(text) => Cmd::new(text, shell),
+            Command::Compound(compound, redirections) => {
+                Cmd::compound(compound, redirections, shell).map(Some)
+            }
+            Command::Function(definition) => Ok(Some(Cmd {
+                command_type: CommandType::Builtin(BuiltInCommand::Define(Rc::clone(definition))),
+                redirections: Vec::new(),
+                assignments: Vec::new(),
+                child: None,
+            })),
+        }
+    }
+
+    /// A pipeline stage that expands `command` once it has been forked.
+    pub fn deferred(command: &Command) -> Self {
+        Cmd {
+            command_type: CommandType::Deferred(command.clone()),
+            redirections: Vec::new(),
+            assignments: Vec::new(),
+            child: None,
+        }
+    }
 
-        let command = Self::build_piped_commands(&mut cmds_split_by_pipe, shell);
+    /// A pipeline stage that runs `compound` with the redirections written after it.
+    pub fn compound(
+        compound: &CompoundCommand,
+        redirections: &CommandText,
+        shell: &mut Shell,
+    ) -> Result<Self, CmdError> {
+        let line = &redirections.text;
+        let mut here_documents = read_here_documents(line, &redirections.here_documents, shell)
+            .map_err(CmdError::Expansion)?;
+        let mut tokens = parse_input(line, shell).map_err(CmdError::Expansion)?;
+        let redirections = Redirection::parse_redirections(
+            &mut tokens,
+            &mut here_documents,
+            shell.options.contains("noclobber"),
+        )
+        .map_err(CmdError::Syntax)?;
+        if let Some(word) = tokens.first() {
+            let message = format!("syntax error near unexpected token `{}'", word.text);
+            return Err(CmdError::Syntax(message));
+        }
+        Ok(Cmd {
+            command_type: CommandType::Compound(compound.clone()),
+            redirections,
+            assignments: Vec::new(),
+            child: None,
+        })
+    }
 
-        command
+    /// Adds `next` as the stage after the last one of this pipeline.
+    pub fn append(&mut self, next: Cmd) {
+        match &mut self.child {
+            Some(child) => child.append(next),
+            None => self.child = Some(Box::new(next)),
+        }
+    }
+
+    /// Replaces the command word of each pipeline stage with its alias. Only a word typed
+    /// without quotes, escapes or expansions is looked up. The replacement is expanded again,
+    /// except for aliases already being expanded, and a value ending in a blank makes the word
+    /// after it a candidate too, as in `alias sudo='sudo '`.
+    fn expand_aliases(tokens: Vec<Word>, shell: &mut Shell) -> Result<Vec<Word>, String> {
+        let aliases = Rc::clone(&shell.aliases);
+        let aliases = aliases.borrow();
+        // each word, and whether it may be an alias
+        let mut words: VecDeque<(Word, bool)> = tokens.into_iter().map(|t| (t, false)).collect();
+        if let Some(first) = words.front_mut() {
+            first.1 = true;
+        }
+        let mut expanded = Vec::new();
+        let mut expanding = HashSet::new();
+        while let Some((word, candidate)) = words.pop_front() {
+            let alias = aliases
+                .get(&word.text)
+                .filter(|_| candidate && word.is_literal() && !expanding.contains(&word.text));
+            if let Some(value) = alias {
+                let mut replacement: VecDeque<(Word, bool)> =
+                    parse_input(value, shell)?.into_iter().map(|t| (t, false)).collect();
+                let blank = value.ends_with([' ', '\t']);
+                if let Some(next) = words.front_mut() {
+                    next.1 |= blank || replacement.is_empty();
+                }
+                if let Some(first) = replacement.front_mut() {
+                    first.1 = true;
+                }
+                expanding.insert(word.text);
+                while let Some(replacement_word) = replacement.pop_back() {
+                    words.push_front(replacement_word);
+                }
+                continue;
+            }
+            // the command word comes after any `NAME=value` words, and a new one after `|`
+            let next_is_candidate = if word == Word::new("|") {
+                expanding.clear();
+                true
+            } else {
+                candidate && split_assignment(&word.text).is_some()
+            };
+            if next_is_candidate && let Some(next) = words.front_mut() {
+                next.1 = true;
+            }
+            expanded.push(word);
+        }
+        Ok(expanded)
     }
 
-    pub fn build_piped_commands(cmd_tokens: &mut Vec<Vec<String>>, shell: &Shell) -> Option<Cmd> {
+    pub fn build_piped_commands(
+        cmd_tokens: &mut Vec<Vec<Word>>,
+        here_documents: &mut Vec<VecDeque<String>>,
+        shell: &Shell,
+    ) -> Result<Option<Cmd>, CmdError> {
         if cmd_tokens.is_empty() {
-            return None;
+            return Ok(None);
         }
 
         let mut current_cmd: Option<Cmd> = None;
 
         while let Some(mut tokens) = cmd_tokens.pop() {
+            let mut cmd_here_documents = here_documents.pop().unwrap_or_default();
             if tokens.is_empty() {
                 continue;
             }
-            let (std_out_file, std_err_file) = Redirection::parse_redirections(&mut tokens);
+            // `<` and `>` compare strings inside `[[ ]]`
+            let redirections = match tokens[0].text == "[[" {
+                true => Vec::new(),
+                false => Redirection::parse_redirections(
+                    &mut tokens,
+                    &mut cmd_here_documents,
+                    shell.options.contains("noclobber"),
+                )
+                .map_err(CmdError::Syntax)?,
+            };
+            let count = tokens.iter().take_while(|t| t.assignment().is_some()).count();
+            let assignments: Vec<(String, AssignmentValue)> = tokens
+                .drain(..count)
+                .filter_map(|t| t.assignment().map(|(target, value)| (target.to_string(), value)))
+                .collect();
+            if tokens.is_empty() && assignments.is_empty() {
+                continue;
+            }
 
-            let command_str = tokens.remove(0);
-            let cmd = Self::get_command_type_from_cmd_name(command_str.as_str(), tokens, &shell);
+            let cmd = match tokens.is_empty() {
+                true => CommandType::Builtin(BuiltInCommand::Assign),
+                false => {
+                    let name = tokens.remove(0);
+                    // only declaration builtins take compound assignments as arguments
+                    let declaration = matches!(
+                        name.text.as_str(),
+                        "declare" | "typeset" | "export" | "readonly" | "local"
+                    );
+                    if name.elements.is_some() || !declaration && tokens.iter().any(|t| t.elements.is_some()) {
+                        return Err(CmdError::Syntax("syntax error near unexpected token `('".to_string()));
+                    }
+                    // functions come before builtins and commands on PATH
+                    match shell.functions.contains_key(&name.text) {
+                        true => {
+                            let args = tokens.into_iter().map(|word| word.text).collect();
+                            CommandType::Builtin(BuiltInCommand::Call(name.text, args))
+                        }
+                        false => Self::get_command_type_from_cmd_name(&name.text, tokens, shell),
+                    }
+                }
+            };
 
             let cmd = Cmd {
                 command_type: cmd,
-                child: match current_cmd {
-                    None => None,
-                    Some(cmd) => Some(Box::new(cmd)),
-                },
-                command_str,
-                redirect_std_out: std_out_file,
-                redirect_std_error: std_err_file,
+                child: current_cmd.map(Box::new),
+                redirections,
+                assignments,
             };
 
             current_cmd = Some(cmd);
         }
-        current_cmd
+        Ok(current_cmd)
     }
 
-    fn get_command_type_from_cmd_name(cmd: &str, args: Vec<String>, shell: &Shell) -> CommandType {
+    fn get_command_type_from_cmd_name(cmd: &str, words: Vec<Word>, shell: &Shell) -> CommandType {
+        let args: Vec<String> = words.iter().map(|word| word.text.clone()).collect();
         match cmd {
-            "exit" => CommandType::Builtin(BuiltInCommand::Exit),
+            "exit" => CommandType::Builtin(BuiltInCommand::Exit(args)),
             "echo" => CommandType::Builtin(BuiltInCommand::Echo(args)),
             "type" => CommandType::Builtin(BuiltInCommand::Type(args)),
-            "pwd" => CommandType::Builtin(BuiltInCommand::PWD),
+            "pwd" => CommandType::Builtin(BuiltInCommand::PWD(args)),
             "cd" => CommandType::Builtin(BuiltInCommand::CD(args)),
             "history" => CommandType::Builtin(BuiltInCommand::History(args)),
+            "trap" => CommandType::Builtin(BuiltInCommand::Trap(args)),
+            "read" => CommandType::Builtin(BuiltInCommand::Read(args)),
+            "mapfile" | "readarray" => CommandType::Builtin(BuiltInCommand::Mapfile(args)),
+            "shopt" => CommandType::Builtin(BuiltInCommand::Shopt(args)),
+            "exec" => CommandType::Builtin(BuiltInCommand::Exec(args)),
+            "set" => CommandType::Builtin(BuiltInCommand::Set(args)),
+            "hash" => CommandType::Builtin(BuiltInCommand::Hash(args)),
+            "rehash" => CommandType::Builtin(BuiltInCommand::Hash(vec!["-r".to_string()])),
+            "command" => Self::get_command_type_for_command(words, shell),
+            "builtin" => match words.split_first() {
+                Some((name, words)) if shell.builtins.contains(&name.text) => {
+                    Self::get_command_type_from_cmd_name(&name.text, words.to_vec(), shell)
+                }
+                _ => CommandType::Builtin(BuiltInCommand::Builtin(args)),
+            },
+            "export" => CommandType::Builtin(BuiltInCommand::Export(words)),
+            "unset" => CommandType::Builtin(BuiltInCommand::Unset(args)),
+            "readonly" => CommandType::Builtin(BuiltInCommand::Readonly(words)),
+            "declare" | "typeset" => CommandType::Builtin(BuiltInCommand::Declare(words)),
+            "alias" => CommandType::Builtin(BuiltInCommand::Alias(args)),
+            "unalias" => CommandType::Builtin(BuiltInCommand::Unalias(args)),
+            "dirs" => CommandType::Builtin(BuiltInCommand::Dirs(args)),
+            "pushd" => CommandType::Builtin(BuiltInCommand::Pushd(args)),
+            "popd" => CommandType::Builtin(BuiltInCommand::Popd(args)),
+            "z" => CommandType::Builtin(BuiltInCommand::Z(args)),
+            "printf" => CommandType::Builtin(BuiltInCommand::Printf(args)),
+            "test" | "[" => CommandType::Builtin(BuiltInCommand::Test(cmd.to_string(), args)),
+            "[[" => CommandType::Builtin(BuiltInCommand::Conditional(words)),
+            "return" => CommandType::Builtin(BuiltInCommand::Return(args)),
+            "local" => CommandType::Builtin(BuiltInCommand::Local(words)),
+            "break" | "continue" => {
+                CommandType::Builtin(BuiltInCommand::LoopControl(cmd.to_string(), args))
+            }
+            "true" | ":" => CommandType::Builtin(BuiltInCommand::Status(0)),
+            "false" => CommandType::Builtin(BuiltInCommand::Status(1)),
+            // a path is run as given; spawning it reports a missing or non-executable file
+            _ if cmd.contains('/') => CommandType::External {
+                args,
+                path: PathBuf::from(cmd),
+                name: PathBuf::from(cmd),
+            },
             _ => {
                 let exe_name = if cfg!(target_os = "windows") && !cmd.ends_with(".exe") {
                     PathBuf::from(format!("{}.exe", cmd))
                 } else {
                     PathBuf::from(cmd)
                 };
-                let executable = shell.executables.iter().find(|exe| exe.name == exe_name);
-                match executable {
-                    Some(exe) => {
-                        if is_executable(&exe.path) {
-                            CommandType::External {
-                                args,
-                                path: exe.path.clone(),
-                                name: exe_name,
-                            }
-                        } else {
-                            CommandType::Unknown(cmd.to_string())
-                        }
-                    }
+                match shell.find_command(&exe_name.to_string_lossy()) {
+                    Some(path) => CommandType::External {
+                        args,
+                        path,
+                        name: exe_name,
+                    },
                     None => CommandType::Unknown(cmd.to_string()),
                 }
             }
         }
     }
 
-    pub fn command_not_found(&self) -> ShellAction {
-        println!("{}: command not found", self.command_str);
-        ShellAction::Continue
+    // `command [-p] name args` runs `name` directly, so it is resolved here like any other
+    // command word; the builtin itself only handles what's left
+    fn get_command_type_for_command(mut words: Vec<Word>, shell: &Shell) -> CommandType {
+        let default_path = words.first().is_some_and(|word| word.text == "-p");
+        if default_path {
+            words.remove(0);
+        }
+        if words.first().is_some_and(|word| word.text == "--") {
+            words.remove(0);
+        }
+        if words.first().is_none_or(|word| word.text.starts_with('-')) {
+            let mut args: Vec<String> = words.into_iter().map(|word| word.text).collect();
+            if default_path {
+                args.insert(0, "-p".to_string());
+            }
+            return CommandType::Builtin(BuiltInCommand::Command(args));
+        }
+
+        let name = words.remove(0).text;
+        if !default_path || name.contains('/') || shell.builtins.contains(&name) {
+            return Self::get_command_type_from_cmd_name(&name, words, shell);
+        }
+        match search_path(DEFAULT_PATH, &name).into_iter().next() {
+            Some(path) => CommandType::External {
+                args: words.into_iter().map(|word| word.text).collect(),
+                path,
+                name: PathBuf::from(name),
+            },
+            None => CommandType::Unknown(name),
+        }
     }
+
     pub fn flatten(&self) -> Vec<&Cmd> {
         let mut cmds = Vec::new();
         let mut current = Some(self);
@@ -1,12 +1,20 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::aliases::Aliases;
+use crate::command_hash::CommandHash;
 use rustyline::{
     completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
     Helper,
 };
 
-pub(crate) struct MyHelper {}
+pub(crate) struct MyHelper {
+    commands: Rc<RefCell<CommandHash>>,
+    aliases: Rc<RefCell<Aliases>>,
+}
 impl MyHelper {
-    pub fn new() -> Self {
-        MyHelper {}
+    pub fn new(commands: Rc<RefCell<CommandHash>>, aliases: Rc<RefCell<Aliases>>) -> Self {
+        MyHelper { commands, aliases }
     }
 }
 impl Completer for MyHelper {
@@ -28,19 +36,9 @@ impl Completer for MyHelper {
             .filter(|cmd| cmd.starts_with(last_word))
             .map(|s| s.to_string())
             .collect();
-        if matches.len() == 0 {
-            if let Ok(path_var) = std::env::var("PATH") {
-                for path in std::env::split_paths(&path_var) {
-                    if let Ok(entries) = std::fs::read_dir(path) {
-                        for entry in entries.flatten() {
-                            let filename = entry.file_name().to_string_lossy().to_string();
-                            if filename.starts_with(last_word) {
-                                matches.push(filename);
-                            }
-                        }
-                    }
-                }
-            }
+        matches.extend(self.aliases.borrow().complete(last_word));
+        if matches.is_empty() {
+            matches = self.commands.borrow_mut().complete(last_word);
         }
 
         matches.sort();
@@ -0,0 +1,473 @@
+use std::ffi::CString;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+
+use crate::arithmetic;
+use crate::parser::Word;
+use crate::shell::Shell;
+use crate::utils::glob_match;
+
+const UNARY_OPERATORS: [&str; 23] = [
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-O", "-G", "-p", "-r", "-s",
+    "-S", "-t", "-u", "-v", "-w", "-x", "-z",
+];
+const BINARY_OPERATORS: [&str; 14] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// Evaluates the arguments of `test` or `[`, following POSIX for up to four arguments so
+/// that strings such as `=` or `!` are taken as operands where they can only be operands.
+pub fn test(args: &[String], shell: &Shell) -> Result<bool, String> {
+    let is_binary =
+        |op: &String| BINARY_OPERATORS.contains(&op.as_str()) || op == "-a" || op == "-o";
+    match args {
+        [] => Ok(false),
+        [arg] => Ok(!arg.is_empty()),
+        [not, arg] if not == "!" => Ok(arg.is_empty()),
+        [op, operand] if UNARY_OPERATORS.contains(&op.as_str()) => unary(op, operand, shell),
+        [op, _] => Err(format!("{}: unary operator expected", op)),
+        [left, op, right] if is_binary(op) => match op.as_str() {
+            "-a" => Ok(!left.is_empty() && !right.is_empty()),
+            "-o" => Ok(!left.is_empty() || !right.is_empty()),
+            _ => binary(left, op, right, None),
+        },
+        [not, rest @ ..] if not == "!" && args.len() <= 4 => test(rest, shell).map(|result| !result),
+        [open, inner @ .., close] if open == "(" && close == ")" && args.len() <= 4 => {
+            test(inner, shell)
+        }
+        [_, op, _] => Err(format!("{}: binary operator expected", op)),
+        _ => {
+            let mut parser =
+                Parser { args, quoted: &[], position: 0, shell, extended: false, rematch: None };
+            let result = parser.or()?;
+            match parser.peek() {
+                None => Ok(result),
+                Some(_) => Err("too many arguments".to_string()),
+            }
+        }
+    }
+}
+
+/// Evaluates the words between `[[` and `]]`. A quoted right-hand side of `==`, `!=` or `=~`
+/// matches literally rather than as a pattern. Besides the result, returns the match and
+/// groups of the last successful `=~` for `BASH_REMATCH`.
+pub fn conditional(words: &[Word], shell: &Shell) -> Result<(bool, Option<Vec<String>>), String> {
+    if words.is_empty() {
+        return Err("syntax error in conditional expression".to_string());
+    }
+    let args: Vec<String> = words.iter().map(|word| word.text.clone()).collect();
+    let quoted: Vec<bool> = words.iter().map(|word| word.quoted).collect();
+    let mut parser = Parser {
+        args: &args,
+        quoted: &quoted,
+        position: 0,
+        shell,
+        extended: true,
+        rematch: None,
+    };
+    let result = parser.or()?;
+    match parser.peek() {
+        None => Ok((result, parser.rematch)),
+        Some(word) => Err(format!(
+            "syntax error in conditional expression: unexpected token `{}'",
+            word
+        )),
+    }
+}
+
+// recursive descent over the arguments: `||` (or `-o`) binds loosest, then `&&` (or `-a`),
+// then `!`
+struct Parser<'a> {
+    args: &'a [String],
+    // which of `args` were quoted; empty for `test`
+    quoted: &'a [bool],
+    position: usize,
+    shell: &'a Shell,
+    // `[[ ]]` rather than `test`
+    extended: bool,
+    rematch: Option<Vec<String>>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.args.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&str> {
+        let arg = self.args.get(self.position)?;
+        self.position += 1;
+        Some(arg)
+    }
+
+    fn eat(&mut self, word: &str) -> bool {
+        let found = self.peek() == Some(word);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let or = if self.extended { "||" } else { "-o" };
+        let mut result = self.and()?;
+        while self.eat(or) {
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let and = if self.extended { "&&" } else { "-a" };
+        let mut result = self.not()?;
+        while self.eat(and) {
+            result &= self.not()?;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.eat("!") {
+            return self.not().map(|result| !result);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        if self.eat("(") {
+            let result = self.or()?;
+            if !self.eat(")") {
+                return Err(self.syntax_error("expected `)'"));
+            }
+            return Ok(result);
+        }
+        let Some(word) = self.next().map(str::to_string) else {
+            return Err(self.syntax_error("argument expected"));
+        };
+        // in `[[ ]]` an operator can't be the operand, so `[[ -n && x ]]` is an error
+        let is_operator = |arg: &&str| self.extended && matches!(*arg, ")" | "&&" | "||");
+        if UNARY_OPERATORS.contains(&word.as_str())
+            && let Some(operand) = self.peek().filter(|arg| !is_operator(arg))
+        {
+            let operand = operand.to_string();
+            self.position += 1;
+            return unary(&word, &operand, self.shell);
+        }
+        let binary_operator = self
+            .peek()
+            .filter(|op| BINARY_OPERATORS.contains(op) || self.extended && *op == "=~")
+            .map(str::to_string);
+        let Some(op) = binary_operator else {
+            return Ok(!word.is_empty());
+        };
+        self.position += 1;
+        let quoted = self.quoted.get(self.position).copied().unwrap_or(false);
+        let Some(right) = self.next().map(str::to_string) else {
+            return Err(self.syntax_error(&format!("argument expected after `{}'", op)));
+        };
+        if !self.extended {
+            return binary(&word, &op, &right, None);
+        }
+        let pattern = match (quoted, op.as_str()) {
+            (true, "=~") => escape_regex(&right),
+            _ => right.clone(),
+        };
+        match op.as_str() {
+            "==" | "=" if quoted => Ok(word == right),
+            "!=" if quoted => Ok(word != right),
+            "==" | "=" => Ok(glob_match(&right, &word)),
+            "!=" => Ok(!glob_match(&right, &word)),
+            "=~" => match regex_match(&pattern, &word)? {
+                Some(groups) => {
+                    self.rematch = Some(groups);
+                    Ok(true)
+                }
+                None => {
+                    self.rematch = Some(Vec::new());
+                    Ok(false)
+                }
+            },
+            _ => binary(&word, &op, &right, Some(self.shell)),
+        }
+    }
+
+    fn syntax_error(&self, detail: &str) -> String {
+        match self.extended {
+            true => "syntax error in conditional expression".to_string(),
+            false => detail.to_string(),
+        }
+    }
+}
+
+fn unary(op: &str, operand: &str, shell: &Shell) -> Result<bool, String> {
+    let metadata = || fs::metadata(operand).ok();
+    let has_mode = |bits: u32| metadata().is_some_and(|m| m.permissions().mode() & bits != 0);
+    Ok(match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-a" | "-e" => metadata().is_some(),
+        "-f" => metadata().is_some_and(|m| m.is_file()),
+        "-d" => metadata().is_some_and(|m| m.is_dir()),
+        "-b" => metadata().is_some_and(|m| m.file_type().is_block_device()),
+        "-c" => metadata().is_some_and(|m| m.file_type().is_char_device()),
+        "-p" => metadata().is_some_and(|m| m.file_type().is_fifo()),
+        "-S" => metadata().is_some_and(|m| m.file_type().is_socket()),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|m| m.file_type().is_symlink()),
+        "-s" => metadata().is_some_and(|m| m.len() > 0),
+        "-g" => has_mode(0o2000),
+        "-u" => has_mode(0o4000),
+        "-k" => has_mode(0o1000),
+        "-O" => metadata().is_some_and(|m| m.uid() == unsafe { libc::geteuid() }),
+        "-G" => metadata().is_some_and(|m| m.gid() == unsafe { libc::getegid() }),
+        "-r" => accessible(operand, libc::R_OK),
+        "-w" => accessible(operand, libc::W_OK),
+        "-x" => accessible(operand, libc::X_OK),
+        "-t" => unsafe { libc::isatty(integer(operand)? as libc::c_int) == 1 },
+        "-v" => shell.get_var(operand).is_some(),
+        _ => return Err(format!("{}: unary operator expected", op)),
+    })
+}
+
+// `shell` is given for `[[ ]]`, whose integer operands are arithmetic expressions
+fn binary(left: &str, op: &str, right: &str, shell: Option<&Shell>) -> Result<bool, String> {
+    let integers = || -> Result<(i64, i64), String> {
+        match shell {
+            Some(shell) => {
+                let lookup = |name: &str| shell.get_var(name);
+                Ok((arithmetic::evaluate(left, &lookup)?, arithmetic::evaluate(right, &lookup)?))
+            }
+            None => Ok((integer(left)?, integer(right)?)),
+        }
+    };
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => integers().map(|(a, b)| a == b)?,
+        "-ne" => integers().map(|(a, b)| a != b)?,
+        "-lt" => integers().map(|(a, b)| a < b)?,
+        "-le" => integers().map(|(a, b)| a <= b)?,
+        "-gt" => integers().map(|(a, b)| a > b)?,
+        "-ge" => integers().map(|(a, b)| a >= b)?,
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(a), Some(b)) => a > b,
+            (a, b) => a.is_some() && b.is_none(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(a), Some(b)) => a < b,
+            (a, b) => a.is_none() && b.is_some(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(a), Ok(b)) => same_file(&a, &b),
+            _ => false,
+        },
+        _ => return Err(format!("{}: binary operator expected", op)),
+    })
+}
+
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+fn accessible(path: &str, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+fn integer(arg: &str) -> Result<i64, String> {
+    arg.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", arg))
+}
+
+/// Matches `text` against the POSIX extended regular expression `pattern`. Returns the
+/// whole match followed by each parenthesized group, empty for groups that didn't take
+/// part, or `None` without a match.
+fn regex_match(pattern: &str, text: &str) -> Result<Option<Vec<String>>, String> {
+    let (Ok(c_pattern), Ok(c_text)) = (CString::new(pattern), CString::new(text)) else {
+        return Ok(None);
+    };
+    let mut regex: libc::regex_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::regcomp(&mut regex, c_pattern.as_ptr(), libc::REG_EXTENDED) } != 0 {
+        return Err(format!("{}: invalid regular expression", pattern));
+    }
+    let mut matches = vec![libc::regmatch_t { rm_so: -1, rm_eo: -1 }; count_groups(pattern) + 1];
+    let status = unsafe {
+        libc::regexec(&regex, c_text.as_ptr(), matches.len(), matches.as_mut_ptr(), 0)
+    };
+    unsafe { libc::regfree(&mut regex) };
+    if status != 0 {
+        return Ok(None);
+    }
+    let groups = matches
+        .iter()
+        .map(|m| match (usize::try_from(m.rm_so), usize::try_from(m.rm_eo)) {
+            (Ok(start), Ok(end)) => text.get(start..end).unwrap_or_default().to_string(),
+            _ => String::new(),
+        })
+        .collect();
+    Ok(Some(groups))
+}
+
+// a quoted `=~` operand matches itself, so each character special in an extended regular
+// expression is escaped
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// the number of `(` groups in a regular expression, skipping escaped parentheses and
+// bracket expressions
+fn count_groups(pattern: &str) -> usize {
+    let mut chars = pattern.chars().peekable();
+    let mut count = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => {
+                // a `]` right after `[` or `[^` is part of the set
+                chars.next_if_eq(&'^');
+                chars.next_if_eq(&']');
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            '(' => count += 1,
+            _ => {}
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell() -> Shell {
+        let mut shell = Shell::for_tests();
+        shell.variables.set("x", "5".to_string()).unwrap();
+        shell
+    }
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn run_test(text: &str) -> Result<bool, String> {
+        test(&args(text), &shell())
+    }
+
+    // `'...'` marks a quoted word
+    fn words(text: &str) -> Vec<Word> {
+        text.split_whitespace()
+            .map(|word| match word.strip_prefix('\'').and_then(|word| word.strip_suffix('\'')) {
+                Some(word) => Word { quoted: true, ..Word::new(word) },
+                None => Word::new(word),
+            })
+            .collect()
+    }
+
+    fn run_conditional(text: &str) -> Result<bool, String> {
+        conditional(&words(text), &shell()).map(|(result, _)| result)
+    }
+
+    #[test]
+    fn test_argument_counts() {
+        assert_eq!(run_test(""), Ok(false));
+        assert_eq!(run_test("x"), Ok(true));
+        assert_eq!(run_test("-n"), Ok(true));
+        assert_eq!(run_test("! x"), Ok(false));
+        assert_eq!(run_test("-z abc"), Ok(false));
+        assert_eq!(run_test("= = ="), Ok(true));
+        assert_eq!(run_test("! = x"), Ok(false));
+        assert_eq!(run_test("( x )"), Ok(true));
+        assert_eq!(run_test("! a = b"), Ok(true));
+        assert_eq!(run_test("( a = a )"), Ok(true));
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(run_test("abc != abd"), Ok(true));
+        assert_eq!(run_test("a < b"), Ok(true));
+        assert_eq!(run_test("10 -gt 9"), Ok(true));
+        assert_eq!(run_test("-3 -le -3"), Ok(true));
+        assert_eq!(run_test("a = b -o c = c"), Ok(true));
+        assert_eq!(run_test("a = a -a b = c"), Ok(false));
+        assert_eq!(run_test("! ( a = b ) -a x"), Ok(true));
+        assert_eq!(run_test("-v x"), Ok(true));
+        assert_eq!(run_test("-v unset_variable"), Ok(false));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(run_test("x y"), Err("x: unary operator expected".to_string()));
+        assert_eq!(run_test("a b c"), Err("b: binary operator expected".to_string()));
+        assert_eq!(run_test("a = a b c"), Err("too many arguments".to_string()));
+        assert_eq!(run_test("abc -eq 1"), Err("abc: integer expression expected".to_string()));
+    }
+
+    #[test]
+    fn conditional_operators() {
+        assert_eq!(run_conditional("a && ! b"), Ok(false));
+        assert_eq!(run_conditional("a && ! ''"), Ok(true));
+        assert_eq!(run_conditional("-z x || -n x"), Ok(true));
+        assert_eq!(run_conditional("! ( a == b && c == c )"), Ok(true));
+        assert_eq!(run_conditional("a < b"), Ok(true));
+        assert_eq!(run_conditional("b > c"), Ok(false));
+        assert_eq!(run_conditional("x+1 -eq 6"), Ok(true));
+        assert_eq!(run_conditional("-v x"), Ok(true));
+    }
+
+    #[test]
+    fn conditional_patterns() {
+        assert_eq!(run_conditional("abc == a*"), Ok(true));
+        assert_eq!(run_conditional("abc = *c"), Ok(true));
+        assert_eq!(run_conditional("abc != ?b?"), Ok(false));
+        assert_eq!(run_conditional("abc == [xyz]*"), Ok(false));
+    }
+
+    #[test]
+    fn conditional_regex() {
+        let (result, rematch) =
+            conditional(&words("key=value =~ ^([a-z]+)=(.*)$"), &shell()).unwrap();
+        assert!(result);
+        assert_eq!(rematch, Some(vec!["key=value".into(), "key".into(), "value".into()]));
+        let (result, rematch) = conditional(&words("abc =~ x"), &shell()).unwrap();
+        assert!(!result);
+        assert_eq!(rematch, Some(Vec::new()));
+    }
+
+    #[test]
+    fn quoted_right_hand_sides_match_literally() {
+        assert_eq!(run_conditional("abc == 'a*'"), Ok(false));
+        assert_eq!(run_conditional("a* == 'a*'"), Ok(true));
+        assert_eq!(run_conditional("abc != '?b?'"), Ok(true));
+        assert_eq!(run_conditional("a.c =~ 'a.c'"), Ok(true));
+        assert_eq!(run_conditional("abc =~ 'a.c'"), Ok(false));
+    }
+
+    #[test]
+    fn conditional_errors() {
+        let error = Err("syntax error in conditional expression".to_string());
+        assert_eq!(run_conditional(""), error);
+        assert_eq!(run_conditional("( a"), error);
+        assert_eq!(run_conditional("a =="), error);
+        assert_eq!(
+            run_conditional("a b"),
+            Err("syntax error in conditional expression: unexpected token `b'".to_string())
+        );
+    }
+}
@@ -1,165 +1,655 @@
-use crate::builtin::{change_directories, echo, exit, history, print_current_dir, type_command};
-use crate::command_input::{BuiltInCommand, Cmd, CommandType};
-use crate::shell::{Shell, ShellAction};
-use crate::utils::write_to_dest;
-use os_pipe::pipe;
-use rustyline::history::History;
-use std::fs::{File, OpenOptions};
-use std::io;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
-
-enum PipeReaderKind {
-    Pipe(os_pipe::PipeReader),
-    Child(std::process::ChildStdout),
+use crate::builtin::{
+    alias, assign, builtin_command, change_directories, command, conditional, declare, dirs, echo,
+    exec, exit, export, hash, history, local, loop_control, mapfile, popd, print_current_dir,
+    printf, pushd, read, readonly, return_command, set, shopt, test, trap, type_command, unalias,
+    unset, z,
+};
+use crate::command_input::{BuiltInCommand, Cmd, CmdError, CommandType};
+use crate::redirection::{install_child_fds, FdTable};
+use crate::utils::{io_error_message, write_to_dest};
+use crate::variables::{is_valid_name, AssignmentValue};
+use crate::parser::parse_input;
+use crate::shell::{Frame, Jump, Shell, ShellAction};
+use crate::syntax::{AndOr, Command as ShellCommand, CompoundCommand, Connector, List, Pipeline};
+use os_pipe::{pipe, PipeReader};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::fd::AsRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::rc::Rc;
+use std::process::{Child, Command, ExitStatus};
+
+/// Standard input of a builtin. The raw fd lets builtins poll it or change terminal modes.
+pub trait Input: io::Read + AsRawFd {}
+impl<T: io::Read + AsRawFd> Input for T {}
+
+enum PipelineChild {
+    Process(Child),
+    // a builtin running in a forked subshell
+    Forked(libc::pid_t),
+    // a stage that ended without leaving a process behind, such as a failed redirection
+    Finished(i32),
+}
+
+impl PipelineChild {
+    // the stage's exit status; a failure to wait for it is reported on `fds` as status 1
+    fn wait(self, fds: &FdTable) -> i32 {
+        match self {
+            PipelineChild::Process(mut child) => match child.wait() {
+                Ok(status) => exit_code(status),
+                Err(e) => {
+                    shell_error(fds, &format!("wait: {}", io_error_message(&e)));
+                    1
+                }
+            },
+            PipelineChild::Forked(pid) => {
+                let mut status = 0;
+                while unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        shell_error(fds, &format!("wait: {}", io_error_message(&e)));
+                        return 1;
+                    }
+                }
+                exit_code(ExitStatus::from_raw(status))
+            }
+            PipelineChild::Finished(status) => status,
+        }
+    }
 }
 
 impl Cmd {
     pub fn execute(&self, shell: &mut Shell) -> ShellAction {
         let pipeline = self.flatten();
 
-        let mut previous_reader: Option<PipeReaderKind> = None;
-        let mut children: Vec<Child> = Vec::new();
+        let mut previous_reader: Option<PipeReader> = None;
+        let mut children: Vec<PipelineChild> = Vec::new();
 
         for (i, cmd) in pipeline.iter().enumerate() {
             let last = i == pipeline.len() - 1;
 
+            let mut fds = match connect_stage(shell, previous_reader.take(), last) {
+                Ok((fds, reader)) => {
+                    previous_reader = reader;
+                    fds
+                }
+                Err(e) => {
+                    shell_error(&shell.fds, &format!("pipe: {}", io_error_message(&e)));
+                    children.push(PipelineChild::Finished(1));
+                    break;
+                }
+            };
+            // a failed stage is skipped; the stages after it see EOF on their input
+            if let Err(message) = fds.apply(&cmd.redirections) {
+                shell_error(&shell.fds, &message);
+                children.push(PipelineChild::Finished(1));
+                continue;
+            }
+
             match &cmd.command_type {
                 // ---------------- BUILTIN ----------------
-                CommandType::Builtin(_) => {
-                    let mut stdin: Box<dyn io::Read> = match previous_reader.take() {
-                        Some(PipeReaderKind::Pipe(r)) => Box::new(r),
-                        Some(PipeReaderKind::Child(r)) => Box::new(r),
-                        None => Box::new(io::stdin()),
-                    };
+                // `exec` without a command keeps its redirections for the rest of the session
+                CommandType::Builtin(BuiltInCommand::Exec(args))
+                    if pipeline.len() == 1 && args.is_empty() =>
+                {
+                    shell.fds = fds;
+                    set_pipe_status(shell, &[0]);
+                    return ShellAction::Continue;
+                }
 
-                    let mut stderr: Box<dyn io::Write> =
-                        if let Some(redir) = &cmd.redirect_std_error {
-                            let file = redir
-                                .options
-                                .open(redir.filename.as_ref().unwrap())
-                                .unwrap();
-                            Box::new(file)
-                        } else {
-                            Box::new(io::stderr())
-                        };
-
-                    let mut stdout: Box<dyn io::Write> = if let Some(redir) = &cmd.redirect_std_out
-                    {
-                        let file = redir
-                            .options
-                            .open(redir.filename.as_ref().unwrap())
-                            .unwrap();
-                        Box::new(file)
-                    } else {
-                        Box::new(io::stdout())
-                    };
+                CommandType::Builtin(_) | CommandType::Compound(_) | CommandType::Deferred(_) => {
+                    let mut stdin = fds.input();
+                    let mut stdout = fds.output(1);
+                    let mut stderr = fds.output(2);
 
-                    if last {
-                        return cmd.execute_builtin(shell, &mut *stdin, &mut *stdout, &mut *stderr);
+                    // like bash, only a lone builtin (or the last stage under `lastpipe`)
+                    // runs in the current shell
+                    if last && (pipeline.len() == 1 || shell.options.contains("lastpipe")) {
+                        // builtins such as `read -u` look fds up through the shell
+                        let saved_fds = mem::replace(&mut shell.fds, fds);
+                        let action =
+                            cmd.execute_builtin(shell, &mut *stdin, &mut *stdout, &mut *stderr);
+                        // close our end of the pipe so upstream stages see EOF/EPIPE
+                        drop(stdin);
+                        drop(mem::replace(&mut shell.fds, saved_fds));
+                        let mut statuses: Vec<i32> =
+                            children.into_iter().map(|child| child.wait(&shell.fds)).collect();
+                        statuses.push(action.status().unwrap_or(shell.last_status));
+                        set_pipe_status(shell, &statuses);
+                        return action;
                     }
 
-                    let (reader, writer) = pipe().unwrap();
-                    let mut writer = writer;
-
-                    cmd.execute_builtin(shell, &mut *stdin, &mut writer, &mut *stderr);
-
-                    drop(writer);
-
-                    previous_reader = Some(PipeReaderKind::Pipe(reader));
-                }
-
-                CommandType::External { path, args, name } => {
-                    let mut command = Command::new(name);
-                    command.args(args);
-
-                    if let Some(reader) = previous_reader.take() {
-                        match reader {
-                            PipeReaderKind::Pipe(r) => {
-                                command.stdin(Stdio::from(r));
-                            }
-                            PipeReaderKind::Child(r) => {
-                                command.stdin(Stdio::from(r));
-                            }
+                    // Run the stage in a subshell so it can't block on a full pipe before
+                    // the downstream stages have been spawned.
+                    let forked = fork_subshell(&mut previous_reader, || {
+                        shell.fds = fds;
+                        let action =
+                            cmd.execute_builtin(shell, &mut *stdin, &mut *stdout, &mut *stderr);
+                        let _ = stdout.flush();
+                        action.status().unwrap_or(shell.last_status)
+                    });
+                    match forked {
+                        Ok(pid) => children.push(PipelineChild::Forked(pid)),
+                        Err(e) => {
+                            shell_error(&shell.fds, &format!("fork: {}", io_error_message(&e)));
+                            children.push(PipelineChild::Finished(1));
                         }
                     }
+                }
 
-                    let mut stdout_file: Option<File> = None;
-
-                    if let Some(redir) = &cmd.redirect_std_out {
-                        let file = redir
-                            .options
-                            .open(redir.filename.as_ref().unwrap())
-                            .unwrap();
-
-                        stdout_file = Some(file);
+                CommandType::External { args, name, path } => {
+                    // run what the shell resolved, under the name the user typed
+                    let mut command = Command::new(path);
+                    command.arg0(name).args(args);
+                    command.env_clear().envs(shell.variables.exported());
+                    // prefixes like `FOO=1 cmd`; array assignments aren't exported
+                    for (target, value) in &cmd.assignments {
+                        if let AssignmentValue::Scalar(value) = value
+                            && is_valid_name(target)
+                        {
+                            command.env(target, value);
+                        }
                     }
 
-                    if let Some(file) = stdout_file {
-                        command.stdout(Stdio::from(file));
-                    } else if !last {
-                        command.stdout(Stdio::piped());
+                    let child_fds = fds.child_fds();
+                    unsafe {
+                        command.pre_exec(move || install_child_fds(&child_fds));
                     }
-                    if let Some(redir) = &cmd.redirect_std_error {
-                        let file = redir
-                            .options
-                            .open(redir.filename.as_ref().unwrap())
-                            .unwrap();
-                        command.stderr(Stdio::from(file));
-                    }
-
-                    let mut child = command.spawn().unwrap();
 
-                    if !last {
-                        let stdout = child.stdout.take().unwrap();
-                        previous_reader = Some(PipeReaderKind::Child(stdout));
+                    let spawned = match is_script_without_interpreter(path) {
+                        true => Err(io::Error::from_raw_os_error(libc::ENOEXEC)),
+                        false => command.spawn(),
+                    };
+                    let not_executable =
+                        |e: &io::Error| e.raw_os_error() == Some(libc::ENOEXEC);
+                    match spawned {
+                        Ok(child) => children.push(PipelineChild::Process(child)),
+                        // like other POSIX shells, run a text file without `#!` as a script
+                        Err(e) if not_executable(&e) && !is_binary_file(path) => {
+                            let forked = fork_subshell(&mut previous_reader, || {
+                                if install_child_fds(&fds.child_fds()).is_err() {
+                                    return 126;
+                                }
+                                shell.fds = FdTable::new();
+                                if shell.assign_temporarily(&cmd.assignments).is_err() {
+                                    return 1;
+                                }
+                                shell.run_script_file(path, &name.to_string_lossy(), args)
+                            });
+                            match forked {
+                                Ok(pid) => children.push(PipelineChild::Forked(pid)),
+                                Err(e) => {
+                                    shell_error(&shell.fds, &format!("fork: {}", io_error_message(&e)));
+                                    children.push(PipelineChild::Finished(1));
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            let reason = match path.is_dir() {
+                                true => "Is a directory".to_string(),
+                                false if not_executable(&e) => {
+                                    format!("cannot execute binary file: {}", io_error_message(&e))
+                                }
+                                false => io_error_message(&e),
+                            };
+                            let message = format!("{}: {}", name.display(), reason);
+                            shell_error(&fds, &message);
+                            children.push(PipelineChild::Finished(spawn_error_status(&e)));
+                        }
                     }
-
-                    children.push(child);
                 }
 
-                CommandType::Unknown(_) => {
-                    return cmd.command_not_found();
+                CommandType::Unknown(name) => {
+                    let action = command_not_found(&fds, name);
+                    children.push(PipelineChild::Finished(action.status().unwrap_or(127)));
                 }
             }
         }
 
-        for mut child in children {
-            child.wait().unwrap();
-        }
-
-        ShellAction::Continue
+        let statuses: Vec<i32> =
+            children.into_iter().map(|child| child.wait(&shell.fds)).collect();
+        set_pipe_status(shell, &statuses);
+        ShellAction::from_status(statuses.last().copied().unwrap_or_default())
     }
 
     pub fn execute_builtin(
         &self,
         shell: &mut Shell,
-        input: &mut dyn io::Read,
+        input: &mut dyn Input,
         output: &mut dyn io::Write,
         error: &mut dyn io::Write,
     ) -> ShellAction {
-        match &self.command_type {
-            CommandType::Builtin(builtin) => {
-                return match builtin {
-                    BuiltInCommand::Exit => exit(),
+        let builtin = match &self.command_type {
+            CommandType::Builtin(builtin) => builtin,
+            CommandType::Compound(compound) => return shell.run_compound(compound),
+            CommandType::Deferred(command) => return shell.run_stage(command),
+            _ => return ShellAction::Continue,
+        };
+        if let BuiltInCommand::Assign = builtin {
+            return assign(shell, &self.assignments, error);
+        }
+        // `FOO=1 builtin` only sets FOO while the builtin runs
+        let saved = match shell.assign_temporarily(&self.assignments) {
+            Ok(saved) => saved,
+            Err(e) => {
+                write_to_dest(error, &format!("bash: {}", e));
+                return ShellAction::Status(1);
+            }
+        };
+        let action = match builtin {
+            BuiltInCommand::Exit(args) => exit(shell, args, error),
+
+            BuiltInCommand::PWD(args) => print_current_dir(shell, args, output, error),
+            BuiltInCommand::CD(args) => change_directories(shell, args, output, error),
 
-                    BuiltInCommand::PWD => print_current_dir(shell, output),
+            BuiltInCommand::Echo(args) => echo(args, output),
+            BuiltInCommand::Type(args) => type_command(shell, args, output, error),
+            BuiltInCommand::History(args) => history(shell, args, output, error),
+            BuiltInCommand::Trap(args) => trap(shell, args, output, error),
+            BuiltInCommand::Read(args) => read(shell, args, input, error),
+            BuiltInCommand::Mapfile(args) => mapfile(shell, args, input, error),
+            BuiltInCommand::Shopt(args) => shopt(shell, args, output, error),
+            BuiltInCommand::Exec(args) => exec(shell, args, error),
+            BuiltInCommand::Set(args) => set(shell, args, output, error),
+            BuiltInCommand::Hash(args) => hash(shell, args, output, error),
+            BuiltInCommand::Command(args) => command(shell, args, output, error),
+            BuiltInCommand::Builtin(args) => builtin_command(args, error),
+            BuiltInCommand::Export(args) => export(shell, args, output, error),
+            BuiltInCommand::Unset(args) => unset(shell, args, error),
+            BuiltInCommand::Readonly(args) => readonly(shell, args, output, error),
+            BuiltInCommand::Declare(args) => declare(shell, args, output, error),
+            BuiltInCommand::Alias(args) => alias(shell, args, output, error),
+            BuiltInCommand::Unalias(args) => unalias(shell, args, error),
+            BuiltInCommand::Dirs(args) => dirs(shell, args, output, error),
+            BuiltInCommand::Pushd(args) => pushd(shell, args, output, error),
+            BuiltInCommand::Popd(args) => popd(shell, args, output, error),
+            BuiltInCommand::Z(args) => z(shell, args, output, error),
+            BuiltInCommand::Printf(args) => printf(shell, args, output, error),
+            BuiltInCommand::Test(name, args) => test(shell, name, args, error),
+            BuiltInCommand::Conditional(args) => conditional(shell, args, error),
+            BuiltInCommand::Return(args) => return_command(shell, args, error),
+            BuiltInCommand::Local(args) => local(shell, args, output, error),
+            BuiltInCommand::Define(definition) => {
+                shell.functions.insert(definition.name.clone(), Rc::clone(definition));
+                ShellAction::Continue
+            }
+            BuiltInCommand::Call(name, args) => shell.call_function(name, args),
+            BuiltInCommand::LoopControl(name, args) => loop_control(shell, name, args, error),
+            BuiltInCommand::Status(status) => ShellAction::from_status(*status),
+            BuiltInCommand::Assign => ShellAction::Continue,
+        };
+        shell.restore_variables(saved);
+        action
+    }
+}
 
-                    BuiltInCommand::CD(args) => {
-                        let path = args.get(0).map(|s| s.as_str()).unwrap_or("~");
-                        change_directories(shell, path, Some(output), error)
+impl Shell {
+    /// Runs the commands of `list` in order, stopping early for `exit`, `break` or
+    /// `continue`.
+    pub fn run_list(&mut self, list: &List) -> ShellAction {
+        let mut action = ShellAction::Continue;
+        for and_or in list {
+            action = self.run_and_or(and_or);
+            if let ShellAction::Exit = action {
+                return action;
+            }
+            if self.jump.is_some() {
+                break;
+            }
+        }
+        action
+    }
+
+    // Each pipeline after `&&` or `||` runs depending on the status of the one before. Only
+    // the failure of the pipeline that ran last triggers the ERR trap.
+    fn run_and_or(&mut self, and_or: &AndOr) -> ShellAction {
+        let pipelines = std::iter::once((None, &and_or.first))
+            .chain(and_or.rest.iter().map(|(connector, pipeline)| (Some(*connector), pipeline)));
+        let mut action = ShellAction::Continue;
+        let mut last_ran = None;
+        for (i, (connector, pipeline)) in pipelines.enumerate() {
+            let skip = match connector {
+                Some(Connector::And) => self.last_status != 0,
+                Some(Connector::Or) => self.last_status == 0,
+                None => false,
+            };
+            if skip {
+                continue;
+            }
+            let condition = i < and_or.rest.len();
+            self.condition_depth += condition as usize;
+            action = self.run_pipeline(pipeline);
+            self.condition_depth -= condition as usize;
+            if let ShellAction::Exit = action {
+                return action;
+            }
+            if self.jump.is_some() {
+                return action;
+            }
+            last_ran = Some(pipeline);
+        }
+        if self.last_status != 0
+            && self.condition_depth == 0
+            && last_ran.is_some_and(|pipeline| !pipeline.negated)
+            && let ShellAction::Exit = self.run_trap("ERR")
+        {
+            return ShellAction::Exit;
+        }
+        action
+    }
+
+    fn run_pipeline(&mut self, pipeline: &Pipeline) -> ShellAction {
+        let mut cmd: Option<Cmd> = None;
+        let stages = pipeline.commands.len();
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            // like bash, every stage of a longer pipeline runs in a subshell, except the
+            // last one under `lastpipe`
+            let in_shell = i == stages - 1 && (stages == 1 || self.options.contains("lastpipe"));
+            let stage = match in_shell {
+                true => Cmd::from_command(command, self),
+                false => Ok(Some(Cmd::deferred(command))),
+            };
+            let stage = match stage {
+                Ok(Some(stage)) => stage,
+                Ok(None) => continue,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    self.last_status = error.status();
+                    // a failed expansion ends a script, as in bash
+                    if let CmdError::Expansion(_) = error
+                        && !self.interactive
+                    {
+                        return ShellAction::Exit;
                     }
+                    return ShellAction::Status(self.last_status);
+                }
+            };
+            match &mut cmd {
+                Some(cmd) => cmd.append(stage),
+                None => cmd = Some(stage),
+            }
+        }
+        let Some(cmd) = cmd else {
+            return ShellAction::Continue;
+        };
+        if let ShellAction::Exit = self.run_trap("DEBUG") {
+            return ShellAction::Exit;
+        }
+        let action = cmd.execute(self);
+        match action.status() {
+            Some(status) if pipeline.negated => self.last_status = (status == 0) as i32,
+            Some(status) => self.last_status = status,
+            None => return action,
+        }
+        if let ShellAction::Exit = self.handle_pending_signals() {
+            return ShellAction::Exit;
+        }
+        ShellAction::from_status(self.last_status)
+    }
 
-                    BuiltInCommand::Echo(args) => echo(args, output),
-                    BuiltInCommand::Type(args) => type_command(shell, args, output),
-                    BuiltInCommand::History(args) => {
-                            history(shell, args, output, error)
+    /// Runs a compound command in the current shell. `( list )` forks a subshell for the list.
+    pub fn run_compound(&mut self, compound: &CompoundCommand) -> ShellAction {
+        match compound {
+            CompoundCommand::Group(list) => self.run_list(list),
+            CompoundCommand::Subshell(list) => {
+                let forked = fork_subshell(&mut None, || {
+                    self.run_list(list);
+                    self.last_status
+                });
+                match forked {
+                    Ok(pid) => ShellAction::from_status(PipelineChild::Forked(pid).wait(&self.fds)),
+                    Err(e) => {
+                        shell_error(&self.fds, &format!("fork: {}", io_error_message(&e)));
+                        ShellAction::Status(1)
+                    }
+                }
+            }
+            CompoundCommand::If { branches, otherwise } => {
+                for (condition, body) in branches {
+                    let action = self.run_condition(condition);
+                    if matches!(action, ShellAction::Exit) || self.jump.is_some() {
+                        return action;
+                    }
+                    if self.last_status == 0 {
+                        return self.run_list(body);
+                    }
+                }
+                match otherwise {
+                    Some(body) => self.run_list(body),
+                    None => ShellAction::Continue,
+                }
+            }
+            CompoundCommand::While { until, condition, body } => {
+                self.run_loop(|shell| {
+                    let action = shell.run_condition(condition);
+                    if matches!(action, ShellAction::Exit) || shell.jump.is_some() {
+                        return Some(action);
                     }
+                    // the condition's status doesn't count as the loop's
+                    ((shell.last_status == 0) != *until).then(|| shell.run_list(body))
+                })
+            }
+            CompoundCommand::For { name, words, body } => {
+                let values = match words {
+                    Some(words) => match parse_input(words, self) {
+                        Ok(words) => words.into_iter().map(|word| word.text).collect(),
+                        Err(e) => {
+                            eprintln!("bash: {}", e);
+                            self.last_status = 1;
+                            return ShellAction::Status(1);
+                        }
+                    },
+                    None => self.positional[1..].to_vec(),
                 };
+                let mut values = values.into_iter();
+                self.run_loop(|shell| {
+                    let value = values.next()?;
+                    if let Err(e) = shell.variables.assign(name, &AssignmentValue::Scalar(value)) {
+                        eprintln!("bash: {}", e);
+                        shell.last_status = 1;
+                        return None;
+                    }
+                    Some(shell.run_list(body))
+                })
+            }
+        }
+    }
+
+    // Expands and runs a command on its own, such as a pipeline stage in the subshell forked
+    // for it.
+    fn run_stage(&mut self, command: &ShellCommand) -> ShellAction {
+        match Cmd::from_command(command, self) {
+            Ok(Some(cmd)) => cmd.execute(self),
+            Ok(None) => ShellAction::Continue,
+            Err(error) => {
+                eprintln!("{}", error);
+                ShellAction::Status(error.status())
+            }
+        }
+    }
+
+    /// Runs the function `name` with `args` as its positional parameters. `FUNCNAME` lists the
+    /// functions being run, innermost first.
+    pub fn call_function(&mut self, name: &str, args: &[String]) -> ShellAction {
+        let Some(definition) = self.functions.get(name).cloned() else {
+            return command_not_found(&self.fds, name);
+        };
+        let positional = [self.positional[0].clone()].into_iter().chain(args.iter().cloned());
+        let positional = mem::replace(&mut self.positional, positional.collect());
+        let loop_depth = mem::take(&mut self.loop_depth);
+        // like bash, a RETURN trap set outside isn't inherited by the function
+        let return_trap = self.traps.remove("RETURN");
+        self.call_stack.push(Frame {
+            name: name.to_string(),
+            locals: Vec::new(),
+        });
+        self.set_function_names();
+
+        let mut action = self.run_stage(&definition.body);
+        if let Some(Jump::Return) = self.jump {
+            self.jump = None;
+        }
+        if let Some(status) = action.status() {
+            self.last_status = status;
+            if let ShellAction::Exit = self.run_trap("RETURN") {
+                action = ShellAction::Exit;
+            }
+        }
+
+        if let Some(frame) = self.call_stack.pop() {
+            self.restore_variables(frame.locals);
+        }
+        self.set_function_names();
+        if let Some(trap) = return_trap {
+            self.traps.insert("RETURN".to_string(), trap);
+        }
+        self.loop_depth = loop_depth;
+        self.positional = positional;
+        action
+    }
+
+    fn set_function_names(&mut self) {
+        let names: BTreeMap<usize, String> =
+            self.call_stack.iter().rev().map(|frame| frame.name.clone()).enumerate().collect();
+        let _ = match names.is_empty() {
+            true => self.variables.unset("FUNCNAME"),
+            false => self.variables.set_array("FUNCNAME", names),
+        };
+    }
+
+    fn run_condition(&mut self, condition: &List) -> ShellAction {
+        self.condition_depth += 1;
+        let action = self.run_list(condition);
+        self.condition_depth -= 1;
+        action
+    }
+
+    // Runs `iteration` until it returns `None`, handling `break` and `continue`. The status
+    // of the loop is that of the last command of its body, or 0 if the body never ran.
+    fn run_loop(&mut self, mut iteration: impl FnMut(&mut Shell) -> Option<ShellAction>) -> ShellAction {
+        self.loop_depth += 1;
+        let mut status = 0;
+        let action = loop {
+            let Some(action) = iteration(self) else {
+                break ShellAction::from_status(status);
+            };
+            status = self.last_status;
+            if let ShellAction::Exit = action {
+                break action;
+            }
+            match self.jump.take() {
+                None | Some(Jump::Continue(1)) => (),
+                Some(Jump::Return) => {
+                    self.jump = Some(Jump::Return);
+                    break ShellAction::from_status(status);
+                }
+                Some(Jump::Break(1)) => break ShellAction::from_status(status),
+                // the rest of the count belongs to the loops around this one
+                Some(Jump::Break(n)) => {
+                    self.jump = Some(Jump::Break(n - 1));
+                    break ShellAction::from_status(status);
+                }
+                Some(Jump::Continue(n)) => {
+                    self.jump = Some(Jump::Continue(n - 1));
+                    break ShellAction::from_status(status);
+                }
+            }
+            // like bash, a command killed by Ctrl-C ends the loop
+            if status == 128 + libc::SIGINT {
+                break ShellAction::from_status(status);
             }
+        };
+        self.loop_depth -= 1;
+        action
+    }
+}
 
-            _ => ShellAction::Continue,
+/// Runs `run` in a forked copy of the shell, which exits with the status it returns.
+fn fork_subshell(
+    previous_reader: &mut Option<PipeReader>,
+    run: impl FnOnce() -> i32,
+) -> io::Result<libc::pid_t> {
+    let _ = io::stdout().flush();
+    match unsafe { libc::fork() } {
+        0 => {
+            // the read end of the pipe to the next stage belongs to that stage
+            drop(previous_reader.take());
+            unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+            let status = run();
+            let _ = io::stdout().flush();
+            unsafe { libc::_exit(status) }
         }
+        -1 => Err(io::Error::last_os_error()),
+        pid => Ok(pid),
     }
 }
+
+/// bash's heuristic: a NUL byte in the first line means the file isn't a script.
+pub fn is_binary_file(path: &Path) -> bool {
+    read_head(path).is_some_and(|head| head.iter().take_while(|b| **b != b'\n').any(|b| *b == 0))
+}
+
+/// A text file without a `#!` line, which exec would reject with ENOEXEC. glibc's execvp
+/// quietly hands such files to /bin/sh instead, so they are recognised before spawning.
+pub fn is_script_without_interpreter(path: &Path) -> bool {
+    read_head(path).is_some_and(|head| !head.starts_with(b"#!")) && !is_binary_file(path)
+}
+
+fn read_head(path: &Path) -> Option<Vec<u8>> {
+    let mut head = vec![0u8; 80];
+    let len = File::open(path).and_then(|mut file| file.read(&mut head)).ok()?;
+    head.truncate(len);
+    Some(head)
+}
+
+// The pipes are connected before the redirections so ones such as `2>&1` can refer to them.
+fn connect_stage(
+    shell: &Shell,
+    input: Option<PipeReader>,
+    last: bool,
+) -> io::Result<(FdTable, Option<PipeReader>)> {
+    let mut fds = shell.fds.try_clone()?;
+    if let Some(reader) = input {
+        fds.set(0, reader)?;
+    }
+    if last {
+        return Ok((fds, None));
+    }
+    let (reader, writer) = pipe()?;
+    fds.set(1, writer)?;
+    Ok((fds, Some(reader)))
+}
+
+/// Records the exit status of every stage of the last pipeline in `PIPESTATUS`.
+fn set_pipe_status(shell: &mut Shell, statuses: &[i32]) {
+    let values = statuses.iter().map(|status| status.to_string()).enumerate().collect();
+    let _ = shell.variables.set_array("PIPESTATUS", values);
+}
+
+/// Reports a failure to run a command on the stderr of `fds`: the shell's, or a command's own
+/// once its redirections have been applied.
+fn shell_error(fds: &FdTable, message: &str) {
+    write_to_dest(&mut *fds.output(2), &format!("bash: {}", message));
+}
+
+fn command_not_found(fds: &FdTable, name: &str) -> ShellAction {
+    shell_error(fds, &format!("{}: command not found", name));
+    ShellAction::Status(127)
+}
+
+/// Like bash: 127 when the program is missing, 126 when it exists but can't be run.
+pub fn spawn_error_status(e: &io::Error) -> i32 {
+    match e.kind() {
+        io::ErrorKind::NotFound => 127,
+        _ => 126,
+    }
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or_default())
+}
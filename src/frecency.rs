@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the database, kept in the same directory as `$HISTFILE`.
+const DATABASE_NAME: &str = ".shell_frecency";
+
+// once the ranks add up to more than this, every rank decays so old entries fade out
+const MAX_TOTAL_RANK: f64 = 9000.0;
+
+#[derive(Debug, Clone, Copy)]
+struct Visits {
+    rank: f64,
+    last_visit: u64,
+}
+
+/// How often and how recently directories were visited, for `z`. Stored one
+/// `path|rank|time` line per directory, the format of the original `z` script. Other shells
+/// share the file, so a change locks it from reading it again until it is written back.
+#[derive(Debug, Default)]
+pub struct Frecency {
+    file: Option<PathBuf>,
+    entries: BTreeMap<PathBuf, Visits>,
+}
+
+impl Frecency {
+    /// Loads the database that belongs with `history_file`. Without a history file the
+    /// directories are only remembered for this session.
+    pub fn load(history_file: &Path) -> Self {
+        let file = match history_file.as_os_str().is_empty() {
+            true => None,
+            false => Some(history_file.with_file_name(DATABASE_NAME)),
+        };
+        let mut frecency = Frecency { file, entries: BTreeMap::new() };
+        let file = frecency.lock(false);
+        frecency.reload(file.as_ref());
+        frecency
+    }
+
+    // Opens the database and waits for an advisory lock on it: a shared one to read it, an
+    // exclusive one to change it. The lock is held until the file is closed.
+    fn lock(&self, exclusive: bool) -> Option<File> {
+        let path = self.file.as_ref()?;
+        let (file, operation) = match exclusive {
+            true => (
+                OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path),
+                libc::LOCK_EX,
+            ),
+            false => (File::open(path), libc::LOCK_SH),
+        };
+        let file = file.ok()?;
+        while unsafe { libc::flock(file.as_raw_fd(), operation) } == -1 {
+            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                return None;
+            }
+        }
+        Some(file)
+    }
+
+    // picks up what other shells have written since the last read
+    fn reload(&mut self, file: Option<&File>) {
+        let mut contents = String::new();
+        let Some(mut file) = file else {
+            return;
+        };
+        if file.read_to_string(&mut contents).is_err() {
+            return;
+        }
+        self.entries.clear();
+        for line in contents.lines() {
+            let mut fields = line.rsplitn(3, '|');
+            let (Some(time), Some(rank), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if let (Ok(rank), Ok(last_visit)) = (rank.parse(), time.parse()) {
+                self.entries.insert(PathBuf::from(path), Visits { rank, last_visit });
+            }
+        }
+    }
+
+    /// Counts a visit to `dir` and saves the database.
+    pub fn record(&mut self, dir: &Path) {
+        let file = self.lock(true);
+        self.reload(file.as_ref());
+        let now = now();
+        let visits = self
+            .entries
+            .entry(dir.to_path_buf())
+            .or_insert(Visits { rank: 0.0, last_visit: now });
+        visits.rank += 1.0;
+        visits.last_visit = now;
+
+        if self.entries.values().map(|visits| visits.rank).sum::<f64>() > MAX_TOTAL_RANK {
+            for visits in self.entries.values_mut() {
+                visits.rank *= 0.99;
+            }
+            self.entries.retain(|_, visits| visits.rank >= 1.0);
+        }
+        self.save(file.as_ref());
+    }
+
+    pub fn remove(&mut self, dir: &Path) -> bool {
+        let file = self.lock(true);
+        self.reload(file.as_ref());
+        let removed = self.entries.remove(dir).is_some();
+        if removed {
+            self.save(file.as_ref());
+        }
+        removed
+    }
+
+    /// Directories whose path contains every fragment in order, best first, with their
+    /// scores. Matching is case-sensitive unless that finds nothing. Directories that no
+    /// longer exist are skipped.
+    pub fn matches(&mut self, fragments: &[String]) -> Vec<(f64, PathBuf)> {
+        let file = self.lock(false);
+        self.reload(file.as_ref());
+        let now = now();
+        let find = |ignore_case: bool| {
+            let mut found: Vec<(f64, PathBuf)> = self
+                .entries
+                .iter()
+                .filter(|(dir, _)| {
+                    contains_in_order(&dir.to_string_lossy(), fragments, ignore_case) && dir.is_dir()
+                })
+                .map(|(dir, visits)| (score(visits, now), dir.clone()))
+                .collect();
+            found.sort_by(|a, b| b.0.total_cmp(&a.0));
+            found
+        };
+        let found = find(false);
+        match found.is_empty() {
+            true => find(true),
+            false => found,
+        }
+    }
+
+    // rewrites the file in place, under the exclusive lock taken to read it
+    fn save(&self, file: Option<&File>) {
+        let Some(mut file) = file else {
+            return;
+        };
+        let contents: String = self
+            .entries
+            .iter()
+            .map(|(dir, visits)| {
+                format!("{}|{}|{}\n", dir.display(), visits.rank, visits.last_visit)
+            })
+            .collect();
+        let _ = file
+            .set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(contents.as_bytes()));
+    }
+}
+
+// the rank weighted by how long ago the last visit was
+fn score(visits: &Visits, now: u64) -> f64 {
+    let age = now.saturating_sub(visits.last_visit);
+    let weight = match age {
+        age if age < 3600 => 4.0,
+        age if age < 86400 => 2.0,
+        age if age < 604800 => 0.5,
+        _ => 0.25,
+    };
+    visits.rank * weight
+}
+
+fn contains_in_order(path: &str, fragments: &[String], ignore_case: bool) -> bool {
+    let path = match ignore_case {
+        true => path.to_lowercase(),
+        false => path.to_string(),
+    };
+    let mut rest = path.as_str();
+    for fragment in fragments {
+        let fragment = match ignore_case {
+            true => fragment.to_lowercase(),
+            false => fragment.clone(),
+        };
+        match rest.find(&fragment) {
+            Some(index) => rest = &rest[index + fragment.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
@@ -0,0 +1,474 @@
+/// The result of expanding one line.
+#[derive(Debug)]
+pub struct Expansion {
+    pub line: String,
+    /// Whether any `!` or `^` was expanded, so the new line should be shown.
+    pub changed: bool,
+    /// Set by the `:p` modifier: the line is shown and stored, but not run.
+    pub print_only: bool,
+}
+
+/// Expands history references in `line` against `history`, oldest entry first: event
+/// designators (`!!`, `!n`, `!-n`, `!prefix`, `!?text?`, `!#`), word designators (`:0`,
+/// `:n`, `^`, `$`, `%`, `*`, `:n-m`) and modifiers (`:h`, `:t`, `:r`, `:e`, `:p`,
+/// `:s/old/new/`, `:gs/old/new/`, `:&`), and `^old^new^` at the start of the line. A `!`
+/// inside single quotes, after a backslash or `${`, or before a blank, `=` or `(` is left
+/// alone.
+pub fn expand(line: &str, history: &[String]) -> Result<Expansion, String> {
+    let mut expansion = Expansion { line: String::new(), changed: false, print_only: false };
+    if !line.contains('!') && !line.starts_with('^') {
+        expansion.line = line.to_string();
+        return Ok(expansion);
+    }
+    let chars: Vec<char> = line.chars().collect();
+    let mut expander = Expander { chars: &chars, position: 0, history, substitution: None };
+    if chars[0] == '^' {
+        expansion.line = expander.quick_substitution()?;
+        expansion.changed = true;
+    }
+    let (mut in_single_quotes, mut in_double_quotes) = (false, false);
+    while let Some(&c) = chars.get(expander.position) {
+        let next = chars.get(expander.position + 1).copied();
+        match c {
+            '\\' if !in_single_quotes => {
+                expansion.line.push(c);
+                expansion.line.extend(next);
+                expander.position += 2;
+                continue;
+            }
+            '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
+            '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
+            // `${!name}` and `${!arr[@]}` are parameter expansions
+            '!' if !in_single_quotes
+                && !expansion.line.ends_with("${")
+                && next.is_some_and(|next| {
+                    !next.is_whitespace() && next != '=' && next != '(' && !(in_double_quotes && next == '"')
+                }) =>
+            {
+                let (text, print_only) = expander.reference(&expansion.line)?;
+                expansion.line.push_str(&text);
+                expansion.changed = true;
+                expansion.print_only |= print_only;
+                continue;
+            }
+            _ => {}
+        }
+        expansion.line.push(c);
+        expander.position += 1;
+    }
+    Ok(expansion)
+}
+
+struct Expander<'a> {
+    chars: &'a [char],
+    position: usize,
+    history: &'a [String],
+    // the last `:s` old and new text, for `:&` and an empty old text
+    substitution: Option<(String, String)>,
+}
+
+impl Expander<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    // the text from `start` up to the current position, for error messages
+    fn text_from(&self, start: usize) -> String {
+        self.chars[start..self.position].iter().collect()
+    }
+
+    // the error for an event that isn't in the history, naming the reference up to the
+    // next blank
+    fn event_not_found(&self, start: usize) -> String {
+        let reference: String = self.chars[start..].iter().take_while(|c| !c.is_whitespace()).collect();
+        format!("{}: event not found", reference)
+    }
+
+    fn previous(&self, start: usize) -> Result<String, String> {
+        self.history.last().cloned().ok_or_else(|| self.event_not_found(start))
+    }
+
+    /// `^old^new^`, the same as `!!:s^old^new^`. The rest of the line is left to expand.
+    fn quick_substitution(&mut self) -> Result<String, String> {
+        let event = self.previous(0)?;
+        let (old, new) = self.substitution_parts()?;
+        self.substitute(&event, &old, &new, false, 0)
+    }
+
+    /// Expands the `!` reference at the current position. `line` is the line expanded so
+    /// far, which `!#` refers to. Returns the text and whether `:p` was given.
+    fn reference(&mut self, line: &str) -> Result<(String, bool), String> {
+        let start = self.position;
+        self.position += 1;
+        let mut search_match = None;
+        let event = match self.peek() {
+            Some('!') => {
+                self.position += 1;
+                self.previous(start)?
+            }
+            Some('#') => {
+                self.position += 1;
+                line.to_string()
+            }
+            // `!$`, `!:2` and the like refer to the previous command
+            Some('^' | '$' | '*' | '%' | ':') => self.previous(start)?,
+            Some('?') => {
+                self.position += 1;
+                let text: String = self.take_while(|c| c != '?' && c != '\n');
+                if self.peek() == Some('?') {
+                    self.position += 1;
+                }
+                let event = self.history.iter().rev().find(|entry| !text.is_empty() && entry.contains(&text));
+                search_match = Some(text);
+                event.ok_or_else(|| self.event_not_found(start))?.clone()
+            }
+            Some(c) if c.is_ascii_digit() || c == '-' && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) => {
+                let sign = if self.peek() == Some('-') {
+                    self.position += 1;
+                    -1
+                } else {
+                    1
+                };
+                let number = sign * self.take_while(|c| c.is_ascii_digit()).parse::<i64>().unwrap_or(0);
+                let index = match number {
+                    number if number < 0 => self.history.len() as i64 + number,
+                    number => number - 1,
+                };
+                let event = usize::try_from(index).ok().and_then(|index| self.history.get(index));
+                event.ok_or_else(|| self.event_not_found(start))?.clone()
+            }
+            _ => {
+                let prefix: String =
+                    self.take_while(|c| !c.is_whitespace() && !matches!(c, ':' | '^' | '$' | '*' | '%'));
+                let event = self.history.iter().rev().find(|entry| entry.starts_with(&prefix));
+                event.ok_or_else(|| self.event_not_found(start))?.clone()
+            }
+        };
+
+        let mut text = self.select_words(&event, search_match.as_deref(), start)?;
+        let mut print_only = false;
+        while self.peek() == Some(':') {
+            let global = matches!(self.peek_at(1), Some('g' | 'a'));
+            let modifier = self.peek_at(if global { 2 } else { 1 });
+            self.position += if global { 3 } else { 2 };
+            text = match modifier {
+                Some('h') => match text.rfind('/') {
+                    Some(index) => text[..index].to_string(),
+                    None => text,
+                },
+                Some('t') => match text.rfind('/') {
+                    Some(index) => text[index + 1..].to_string(),
+                    None => text,
+                },
+                Some('r') => match suffix_index(&text) {
+                    Some(index) => text[..index].to_string(),
+                    None => text,
+                },
+                Some('e') => match suffix_index(&text) {
+                    Some(index) => text[index..].to_string(),
+                    None => text,
+                },
+                Some('p') => {
+                    print_only = true;
+                    text
+                }
+                Some('s') => {
+                    let (old, new) = self.substitution_parts()?;
+                    self.substitute(&text, &old, &new, global, start)?
+                }
+                Some('&') => {
+                    let Some((old, new)) = self.substitution.clone() else {
+                        return Err(format!("{}: no previous substitution", self.text_from(start)));
+                    };
+                    self.substitute(&text, &old, &new, global, start)?
+                }
+                _ => {
+                    return Err(format!("{}: unrecognized history modifier", self.text_from(start)));
+                }
+            };
+        }
+        Ok((text, print_only))
+    }
+
+    // a word designator after the event, if any: `:n`, `:n-m`, `:^`, `:$`, `:*`, `:%`, or
+    // `^`, `$`, `*` and `%` without the colon
+    fn select_words(&mut self, event: &str, search_match: Option<&str>, start: usize) -> Result<String, String> {
+        let designator_start = match (self.peek(), self.peek_at(1)) {
+            (Some(':'), Some(c)) if c.is_ascii_digit() || matches!(c, '^' | '$' | '*' | '%' | '-') => {
+                self.position + 1
+            }
+            (Some('^' | '$' | '*' | '%'), _) => self.position,
+            _ => return Ok(event.to_string()),
+        };
+        self.position = designator_start;
+        let words = split_words(event);
+        let last = words.len().saturating_sub(1);
+        let bad_word = |expander: &Self| format!("{}: bad word specifier", expander.text_from(start));
+
+        let first = match self.peek() {
+            Some('^') => {
+                self.position += 1;
+                1
+            }
+            Some('$') => {
+                self.position += 1;
+                last
+            }
+            Some('*') => {
+                self.position += 1;
+                return Ok(words.get(1..).map(|words| words.join(" ")).unwrap_or_default());
+            }
+            Some('%') => {
+                self.position += 1;
+                let word = search_match.and_then(|text| words.iter().find(|word| word.contains(text)));
+                return word.cloned().ok_or_else(|| bad_word(self));
+            }
+            Some('-') => 0,
+            _ => self.take_while(|c| c.is_ascii_digit()).parse().unwrap_or(0),
+        };
+        let end = match self.peek() {
+            Some('*') => {
+                self.position += 1;
+                Some(last)
+            }
+            Some('-') => {
+                self.position += 1;
+                match self.peek() {
+                    Some('$') => {
+                        self.position += 1;
+                        Some(last)
+                    }
+                    Some(c) if c.is_ascii_digit() => self.take_while(|c| c.is_ascii_digit()).parse().ok(),
+                    // `n-` stops before the last word
+                    _ => last.checked_sub(1),
+                }
+            }
+            _ => Some(first),
+        };
+        // `n*` with n past the last word is empty rather than an error
+        if end == Some(last) && first == last + 1 {
+            return Ok(String::new());
+        }
+        match end {
+            Some(end) if first <= end && end <= last && !words.is_empty() => Ok(words[first..=end].join(" ")),
+            _ => Err(bad_word(self)),
+        }
+    }
+
+    // the old and new text of `:s/old/new/` or `^old^new^`, with the current position on
+    // the delimiter; the last delimiter may be left off at the end of the line
+    fn substitution_parts(&mut self) -> Result<(String, String), String> {
+        let Some(delimiter) = self.peek() else {
+            return Ok(self.substitution.clone().unwrap_or_default());
+        };
+        self.position += 1;
+        let mut parts = [String::new(), String::new()];
+        for part in &mut parts {
+            while let Some(c) = self.peek() {
+                self.position += 1;
+                match c {
+                    c if c == delimiter => break,
+                    '\\' if self.peek() == Some(delimiter) => {
+                        part.push(delimiter);
+                        self.position += 1;
+                    }
+                    c => part.push(c),
+                }
+            }
+        }
+        let [old, new] = parts;
+        let old = match old.is_empty() {
+            true => match &self.substitution {
+                Some((old, _)) => old.clone(),
+                None => return Err("no previous substitution".to_string()),
+            },
+            false => old,
+        };
+        self.substitution = Some((old.clone(), new.clone()));
+        Ok((old, new))
+    }
+
+    // replaces the first `old` in `text`, or every one when `global`; `&` in `new` stands
+    // for `old` and `\&` for a literal `&`
+    fn substitute(&self, text: &str, old: &str, new: &str, global: bool, start: usize) -> Result<String, String> {
+        if !text.contains(old) {
+            return Err(format!("{}: substitution failed", self.text_from(start)));
+        }
+        let mut replacement = String::new();
+        let mut chars = new.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('&') => replacement.push('&'),
+                    Some(c) => {
+                        replacement.push('\\');
+                        replacement.push(c);
+                    }
+                    None => replacement.push('\\'),
+                },
+                '&' => replacement.push_str(old),
+                c => replacement.push(c),
+            }
+        }
+        Ok(match global {
+            true => text.replace(old, &replacement),
+            false => text.replacen(old, &replacement, 1),
+        })
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.peek().filter(|c| predicate(*c)) {
+            taken.push(c);
+            self.position += 1;
+        }
+        taken
+    }
+}
+
+// where the `.suffix` of the last path component starts
+fn suffix_index(text: &str) -> Option<usize> {
+    let index = text.rfind('.')?;
+    match text.rfind('/') {
+        Some(slash) if slash > index => None,
+        _ => Some(index),
+    }
+}
+
+// the words of a history entry for word designators: blank-separated, with quoted text
+// kept together and runs of `|&;<>()` as words of their own
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => {
+                quote = None;
+                word.push(c);
+            }
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.push(c);
+            }
+            (None, '\\') => {
+                word.push(c);
+                word.extend(chars.next());
+            }
+            (None, c) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            (None, '|' | '&' | ';' | '<' | '>' | '(' | ')') => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                let mut operator = c.to_string();
+                while let Some(c) = chars.next_if(|c| matches!(c, '|' | '&' | ';' | '<' | '>')) {
+                    operator.push(c);
+                }
+                words.push(operator);
+            }
+            (None, c) => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HISTORY: [&str; 3] =
+        ["echo one", "ls -l /usr/local/lib/file.tar.gz", "grep foo bar.txt baz.txt"];
+
+    fn history() -> Vec<String> {
+        HISTORY.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    fn expanded(line: &str) -> String {
+        expand(line, &history()).unwrap().line
+    }
+
+    #[test]
+    fn event_designators() {
+        assert_eq!(expanded("!!"), "grep foo bar.txt baz.txt");
+        assert_eq!(expanded("!-2"), "ls -l /usr/local/lib/file.tar.gz");
+        assert_eq!(expanded("!1"), "echo one");
+        assert_eq!(expanded("!ec"), "echo one");
+        assert_eq!(expanded("!?usr?"), "ls -l /usr/local/lib/file.tar.gz");
+        assert_eq!(expanded("x !#"), "x x ");
+        assert_eq!(expanded("sudo !!"), "sudo grep foo bar.txt baz.txt");
+    }
+
+    #[test]
+    fn word_designators() {
+        assert_eq!(expanded("!!:0"), "grep");
+        assert_eq!(expanded("!!:1"), "foo");
+        assert_eq!(expanded("!!:^"), "foo");
+        assert_eq!(expanded("!!:$"), "baz.txt");
+        assert_eq!(expanded("!$"), "baz.txt");
+        assert_eq!(expanded("!!:*"), "foo bar.txt baz.txt");
+        assert_eq!(expanded("!!:1-2"), "foo bar.txt");
+        assert_eq!(expanded("!!:2-"), "bar.txt");
+        assert_eq!(expanded("!?usr?:%"), "/usr/local/lib/file.tar.gz");
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(expanded("!-2:2:h"), "/usr/local/lib");
+        assert_eq!(expanded("!-2:$:t"), "file.tar.gz");
+        assert_eq!(expanded("!-2:$:r"), "/usr/local/lib/file.tar");
+        assert_eq!(expanded("!-2:$:e"), ".gz");
+        assert_eq!(expanded("!!:s/foo/qux/"), "grep qux bar.txt baz.txt");
+        assert_eq!(expanded("!!:gs/.txt/.md/"), "grep foo bar.md baz.md");
+        assert_eq!(expanded("^foo^qux^"), "grep qux bar.txt baz.txt");
+    }
+
+    #[test]
+    fn print_only() {
+        let expansion = expand("!!:p", &history()).unwrap();
+        assert_eq!(expansion.line, "grep foo bar.txt baz.txt");
+        assert!(expansion.print_only);
+        assert!(expansion.changed);
+    }
+
+    #[test]
+    fn lines_left_alone() {
+        let lines = [
+            "echo '!!'",
+            "echo \\!!",
+            "echo ! x",
+            "[ ! -e x ]",
+            "x!=y",
+            "echo ${!x[@]}",
+            "echo ${!name}",
+        ];
+        for line in lines {
+            let expansion = expand(line, &history()).unwrap();
+            assert_eq!(expansion.line, line);
+            assert!(!expansion.changed, "{}", line);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(expand("!nope", &history()).unwrap_err(), "!nope: event not found");
+        assert_eq!(expand("!!", &[]).unwrap_err(), "!!: event not found");
+        assert_eq!(expand("!!:9", &history()).unwrap_err(), "!!:9: bad word specifier");
+        assert_eq!(
+            expand("!!:s/nope/x/", &history()).unwrap_err(),
+            "!!:s/nope/x/: substitution failed"
+        );
+    }
+}
@@ -1,22 +1,33 @@
+mod aliases;
+mod arithmetic;
+mod command_hash;
 mod command_input;
 mod parser;
+mod printf;
 use rustyline::error::ReadlineError;
 #[allow(unused_imports)]
 use std::io::{self, Write};
-use std::io::{stderr, stdout};
 
 mod shell;
 use shell::Shell;
 
-use crate::{command_input::Cmd, shell::ShellAction};
-use crate::builtin::history;
+use crate::redirection::find_here_documents;
+use crate::shell::ShellAction;
+use crate::syntax::SyntaxError;
 
 mod builtin;
 
 mod completion_helper;
+mod conditional;
 mod execute;
+mod frecency;
+mod history_expansion;
 mod utils;
 mod redirection;
+mod signals;
+mod special_variables;
+mod syntax;
+mod variables;
 
 fn main() {
     let builtins: Vec<String> = vec![
@@ -26,13 +37,66 @@ fn main() {
         "cd".to_string(),
         "pwd".to_string(),
         "history".to_string(),
+        "trap".to_string(),
+        "read".to_string(),
+        "mapfile".to_string(),
+        "readarray".to_string(),
+        "shopt".to_string(),
+        "exec".to_string(),
+        "set".to_string(),
+        "hash".to_string(),
+        "rehash".to_string(),
+        "command".to_string(),
+        "export".to_string(),
+        "unset".to_string(),
+        "readonly".to_string(),
+        "declare".to_string(),
+        "typeset".to_string(),
+        "alias".to_string(),
+        "unalias".to_string(),
+        "dirs".to_string(),
+        "pushd".to_string(),
+        "popd".to_string(),
+        "z".to_string(),
+        "printf".to_string(),
+        "test".to_string(),
+        "builtin".to_string(),
+        "[".to_string(),
+        "return".to_string(),
+        "local".to_string(),
+        "break".to_string(),
+        "continue".to_string(),
+        ":".to_string(),
+        "true".to_string(),
+        "false".to_string(),
     ];
     let mut shell = Shell::new("PATH", "$ ", builtins, "HISTFILE");
 
     loop {
-        let input = match shell.read_line.readline(&shell.prompt) {
+        if let ShellAction::Exit = shell.handle_pending_signals() {
+            shell.save_history();
+            break;
+        }
+
+        signals::set_at_prompt(true);
+        // completion reads the hash table, so pick up PATH changes first
+        shell.sync_command_path();
+        let prompt = shell.prompt.clone();
+        let line = shell.read_input_line(&prompt);
+        signals::set_at_prompt(false);
+
+        let line = match line {
             Ok(line) => line,
 
+            Err(ReadlineError::Interrupted) if signals::has_pending() => continue,
+            // the editor reads Ctrl-C as a key, so a trapped INT is delivered from here
+            Err(ReadlineError::Interrupted) if shell.traps.contains_key("INT") => {
+                if let ShellAction::Exit = shell.run_trap("INT") {
+                    shell.save_history();
+                    break;
+                }
+                continue;
+            }
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
                 break;
@@ -45,35 +109,115 @@ fn main() {
                 println!("Error: {:?}", err);
                 break;
             }
+        };
+        let leading_blank = line.starts_with([' ', '\t']);
+        let input = line.trim().to_string();
+
+        if let ShellAction::Exit = shell.handle_pending_signals() {
+            shell.save_history();
+            break;
         }
-        .trim()
-        .to_string();
 
         if input.is_empty() {
             continue;
         }
+        shell.line_number += 1;
+        let input = match shell.options.contains("histexpand") {
+            true => match history_expansion::expand(&input, &shell.history_entries()) {
+                Ok(expansion) => {
+                    if expansion.changed {
+                        println!("{}", expansion.line);
+                    }
+                    // `:p` only shows the command, and stores it to be edited or rerun
+                    if expansion.print_only {
+                        shell.add_history(&expansion.line, leading_blank);
+                        continue;
+                    }
+                    expansion.line
+                }
+                Err(message) => {
+                    eprintln!("{}", message);
+                    continue;
+                }
+            },
+            false => input,
+        };
+        let input = read_here_document_lines(&mut shell, input);
+        let Some(input) = read_continuation_lines(&mut shell, input) else {
+            continue;
+        };
 
-        let command = Cmd::new(&input, &shell);
-        shell.read_line.add_history_entry(input).unwrap();
-        match command {
-            None => continue,
-            Some(cmd) => match cmd.execute(&mut shell) {
-                ShellAction::Continue => continue,
-                ShellAction::Exit => {
-                    let file = shell.history_file.clone();
-                    history(
-                        &mut shell,
-                        &vec!["-a".to_string(), file.to_str().unwrap_or_default().to_string()],
-                        &mut stdout(),
-                        &mut stderr()
+        shell.add_history(&input, leading_blank);
+        match shell.run_command(&input) {
+            ShellAction::Continue | ShellAction::Status(_) => continue,
+            ShellAction::Exit => {
+                shell.save_history();
+                break;
+            },
+        }
+    }
+    shell.run_trap("EXIT");
+    std::process::exit(shell.last_status);
+}
+
+// Appends the body lines of any here-documents opened on the last line of `input`,
+// prompting with `> `.
+fn read_here_document_lines(shell: &mut Shell, mut input: String) -> String {
+    let last_line = input.rsplit('\n').next().unwrap_or_default();
+    for here_document in find_here_documents(last_line) {
+        loop {
+            match shell.read_input_line("> ") {
+                Ok(line) => {
+                    input.push('\n');
+                    input.push_str(&line);
+                    if here_document.is_delimiter(&line) {
+                        break;
+                    }
+                }
+                Err(_) => {
+                    eprintln!(
+                        "warning: here-document delimited by end-of-file (wanted `{}')",
+                        here_document.delimiter
                     );
                     break;
-                },
-                ShellAction::Error(msg) => {
-                    writeln!(&mut io::stderr(), "{}", msg).unwrap();
-                    continue;
                 }
-            },
+            }
         }
     }
+    input
+}
+
+// Reads more lines with `> ` while `input` stops inside a command, such as a loop without
+// its `done`. At the end of input the command is run as it is, which reports the syntax
+// error. `None` when Ctrl-C cancels the command.
+fn read_continuation_lines(shell: &mut Shell, mut input: String) -> Option<String> {
+    while let Err(SyntaxError::Incomplete) = syntax::parse(&input) {
+        signals::set_at_prompt(true);
+        let line = shell.read_input_line("> ");
+        signals::set_at_prompt(false);
+        let line = match line {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => return None,
+            Err(_) => break,
+        };
+        let line = match shell.options.contains("histexpand") {
+            true => match history_expansion::expand(&line, &shell.history_entries()) {
+                Ok(expansion) => {
+                    if expansion.changed {
+                        println!("{}", expansion.line);
+                    }
+                    expansion.line
+                }
+                Err(message) => {
+                    eprintln!("{}", message);
+                    return None;
+                }
+            },
+            false => line,
+        };
+        input.push('\n');
+        input.push_str(&line);
+        input = read_here_document_lines(shell, input);
+    }
+    Some(input)
 }
@@ -1,47 +1,254 @@
-use std::{iter::Peekable, str::CharIndices, vec};
+use std::ffi::{CStr, CString};
+use std::{env, iter::Peekable, str::CharIndices, vec};
 
-pub fn parse_input(input: &str) -> Vec<String> {
-    let mut tokens: Vec<String> = Vec::new();
-    let mut token: Option<String> = None;
+use crate::arithmetic;
+use crate::redirection::{is_operator, operator_at};
+use crate::shell::Shell;
+use crate::variables::{is_valid_name, split_assignment, split_subscript, AssignmentValue};
+
+/// The result of a `$` expansion.
+enum Expansion {
+    Text(String),
+    // `$@` and `${arr[@]}` stay separate words even inside double quotes
+    Words(Vec<String>),
+}
+
+/// A word of a command line after expansion and quote removal.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Word {
+    pub text: String,
+    /// Some of the word was quoted or escaped.
+    pub quoted: bool,
+    /// Some of the word came from a parameter or `~` expansion.
+    pub expanded: bool,
+    /// The elements of a compound assignment such as `name=(a b)`, whose text is `name=`.
+    pub elements: Option<Vec<String>>,
+}
+
+impl Word {
+    pub fn new(text: &str) -> Self {
+        Word {
+            text: text.to_string(),
+            ..Word::default()
+        }
+    }
+
+    /// Whether the word is exactly as typed, with nothing quoted or expanded, as an alias
+    /// name or an operator such as `|` must be.
+    pub fn is_literal(&self) -> bool {
+        !self.quoted && !self.expanded
+    }
+
+    /// The target and value of an assignment word such as `name=value` or `name=(a b)`.
+    pub fn assignment(&self) -> Option<(&str, AssignmentValue)> {
+        let (target, value) = split_assignment(&self.text)?;
+        match &self.elements {
+            Some(elements) => Some((target, AssignmentValue::Compound(elements.clone()))),
+            None => Some((target, AssignmentValue::Scalar(value.to_string()))),
+        }
+    }
+}
+
+// The words of a command line as they are built up.
+#[derive(Default)]
+struct WordBuilder {
+    words: Vec<Word>,
+    current: Option<Word>,
+}
+
+impl WordBuilder {
+    fn word(&mut self) -> &mut Word {
+        self.current.get_or_insert_with(Word::default)
+    }
+
+    fn push(&mut self, c: char) {
+        self.word().text.push(c);
+    }
+
+    fn push_str(&mut self, value: &str) {
+        self.word().text.push_str(value);
+    }
+
+    fn finish(&mut self) {
+        if let Some(word) = self.current.take() {
+            self.words.push(word);
+        }
+    }
+
+    // `2` in `2>file`, but not in `"2">file`, `$n>file`, `2&>file` or the target of `>&2>file`
+    fn at_fd_prefix(&self, operator: &str) -> bool {
+        self.current.as_ref().is_some_and(|word| {
+            word.is_literal()
+                && !operator.starts_with('&')
+                && !word.text.is_empty()
+                && word.text.bytes().all(|b| b.is_ascii_digit())
+        }) && !self.words.last().is_some_and(is_operator)
+    }
+
+    // unquoted expansions are split into separate words on whitespace
+    fn push_split(&mut self, value: &str) {
+        if value.starts_with(char::is_whitespace) {
+            self.finish();
+        }
+        for (i, part) in value.split_whitespace().enumerate() {
+            if i > 0 {
+                self.finish();
+            }
+            self.push_str(part);
+            self.word().expanded = true;
+        }
+        if value.ends_with(char::is_whitespace) {
+            self.finish();
+        }
+    }
+}
+
+pub fn parse_input(input: &str, shell: &mut Shell) -> Result<Vec<Word>, String> {
+    let mut words = WordBuilder::default();
+    // the `name=` word and where the elements of a compound assignment `name=(a b)` start
+    let mut compound: Option<(Word, usize)> = None;
 
     let token_delimiters = ['"', '\''];
     let mut iter = input.char_indices().peekable();
-    while let Some((_, c)) = iter.next() {
+    while let Some((i, c)) = iter.next() {
         if c == '\\' {
-            parse_escape(&mut iter, &mut token, &vec![]);
+            if let Some((_, next_c)) = iter.next() {
+                words.push(next_c);
+                words.word().quoted = true;
+            }
             continue;
         }
-        if token_delimiters.contains(&c) {
-            match parse_delimiter(&mut iter, c) {
-                Some(t) => match &token {
-                    Some(_tok) => {
-                        token.get_or_insert_with(String::new).push_str(&t);
-                        push_token(&mut token, &mut tokens);
-                    }
-                    None => {
-                        token.get_or_insert_with(String::new).push_str(&t);
+        if c == '$' {
+            // the value of an assignment isn't split into words, and nothing in `[[ ]]` is
+            let unsplit = words.current.as_ref().is_some_and(|w| split_assignment(&w.text).is_some())
+                || words.words.first().is_some_and(|w| w.text == "[[");
+            match parse_expansion(&mut iter, shell)? {
+                Some(Expansion::Text(value)) if unsplit => {
+                    words.push_str(&value);
+                    words.word().expanded = true;
+                }
+                Some(Expansion::Words(values)) if unsplit => {
+                    words.push_str(&values.join(" "));
+                    words.word().expanded = true;
+                }
+                Some(Expansion::Text(value)) => words.push_split(&value),
+                Some(Expansion::Words(values)) => {
+                    for (i, value) in values.iter().enumerate() {
+                        if i > 0 {
+                            words.finish();
+                        }
+                        words.push_split(value);
                     }
-                },
-                None => (),
+                }
+                None => words.push(c),
+            }
+            continue;
+        }
+        // `~` starts a word, or the value of an assignment
+        if c == '~'
+            && words.current.as_ref().is_none_or(|w| w.text.ends_with('=') && split_assignment(&w.text).is_some())
+        {
+            let mut prefix = String::new();
+            while let Some((_, c)) = iter.next_if(|&(_, c)| c.is_alphanumeric() || "+-_.".contains(c)) {
+                prefix.push(c);
+            }
+            match expand_tilde(&prefix, shell) {
+                Some(dir) => {
+                    words.push_str(&dir);
+                    words.word().expanded = true;
+                }
+                None => words.push_str(&format!("~{}", prefix)),
+            }
+            continue;
+        }
+        if token_delimiters.contains(&c) {
+            // `"$@"` and `"${arr[@]}"` end the word after each element but the last
+            if let Some(mut pieces) = parse_delimiter(&mut iter, c, shell)? {
+                let last = pieces.pop().unwrap_or_default();
+                for piece in pieces {
+                    words.push_str(&piece);
+                    words.word().quoted = true;
+                    words.finish();
+                }
+                words.push_str(&last);
+            }
+            // this also keeps empty quotes such as `trap '' INT` as an empty argument
+            words.word().quoted = true;
+            continue;
+        }
+        if c == '(' && compound.is_none()
+            && let Some(prefix) =
+                words.current.take_if(|w| w.text.ends_with('=') && split_assignment(&w.text).is_some())
+        {
+            compound = Some((prefix, words.words.len()));
+            continue;
+        }
+        if c == ')'
+            && let Some((mut prefix, start)) = compound.take()
+        {
+            words.finish();
+            let elements = words.words.split_off(start);
+            prefix.elements = Some(elements.into_iter().map(|word| word.text).collect());
+            words.current = Some(prefix);
+            continue;
+        }
+        // an unquoted redirection operator is a word of its own, with the digits of its fd,
+        // even when it is written against the word before it as in `echo hi>file`
+        if matches!(c, '<' | '>' | '&')
+            && let Some(operator) = operator_at(&input[i..])
+        {
+            if !words.at_fd_prefix(operator) {
+                words.finish();
+            }
+            words.push_str(operator);
+            words.finish();
+            for _ in 1..operator.len() {
+                iter.next();
             }
-
             continue;
         }
         if c == ' ' {
-            push_token(&mut token, &mut tokens);
+            words.finish();
             continue;
         }
-        add_to_token(&mut token, c);
+        words.push(c);
+    }
+    words.finish();
+
+    Ok(words.words)
+}
+
+/// `~` is `$HOME`, `~+` and `~-` are `$PWD` and `$OLDPWD`, `~N`, `~+N` and `~-N` are entries
+/// of the directory stack, and `~user` is that user's home directory.
+fn expand_tilde(prefix: &str, shell: &Shell) -> Option<String> {
+    match prefix {
+        "" => shell
+            .get_var("HOME")
+            .or_else(|| env::home_dir().map(|home| home.display().to_string())),
+        "+" => shell.get_var("PWD"),
+        "-" => shell.get_var("OLDPWD"),
+        _ if prefix.trim_start_matches(['+', '-']).starts_with(|c: char| c.is_ascii_digit()) => {
+            let index = shell.dir_stack_index(prefix)?;
+            Some(shell.directories()[index].display().to_string())
+        }
+        user => home_directory(user),
     }
-    push_token(&mut token, &mut tokens);
+}
 
-    tokens
+fn home_directory(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr((*entry).pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
 }
 
 fn parse_escape(
     iter: &mut Peekable<CharIndices>,
     token: &mut Option<String>,
-    escaped_chars: &Vec<char>,
+    escaped_chars: &[char],
 ) {
     if let Some(&(_, next_c)) = iter.peek() {
         if escaped_chars.contains(&next_c) || escaped_chars.is_empty() {
@@ -55,12 +262,322 @@ fn parse_escape(
 fn add_to_token(token: &mut Option<String>, value: char) {
     token.get_or_insert_with(String::new).push(value);
 }
-fn push_token(token: &mut Option<String>, tokens: &mut Vec<String>) {
-    if let Some(t) = token.take() {
-        tokens.push(t);
+
+/// Expands `$name`, `${...}`, special parameters such as `$?` and `$#`, and positional
+/// parameters after a `$`. Returns `None` when the `$` is literal.
+fn parse_expansion(
+    iter: &mut Peekable<CharIndices>,
+    shell: &mut Shell,
+) -> Result<Option<Expansion>, String> {
+    let Some(&(_, c)) = iter.peek() else {
+        return Ok(None);
+    };
+    let mut name = String::new();
+    match c {
+        '{' => {
+            iter.next();
+            let content = braced_content(iter);
+            return expand_braces(&content, shell).map(Some);
+        }
+        '@' => {
+            iter.next();
+            return Ok(Some(Expansion::Words(shell.positional[1..].to_vec())));
+        }
+        '?' | '#' | '*' | '$' => {
+            iter.next();
+            name.push(c);
+        }
+        // only one digit: `$10` is `${1}0`
+        c if c.is_ascii_digit() => {
+            iter.next();
+            name.push(c);
+        }
+        c if c == '_' || c.is_ascii_alphabetic() => {
+            while let Some(&(_, c)) = iter.peek() {
+                if c != '_' && !c.is_ascii_alphanumeric() {
+                    break;
+                }
+                name.push(c);
+                iter.next();
+            }
+        }
+        _ => return Ok(None),
+    }
+    Ok(Some(Expansion::Text(shell.get_var(&name).unwrap_or_default())))
+}
+
+// Everything up to the `}` that closes `${`, skipping nested `${...}` and quoted text, so
+// `${x:-${y}}` and `${x:-'}'}` end where they should.
+fn braced_content(iter: &mut Peekable<CharIndices>) -> String {
+    let mut content = String::new();
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    while let Some((_, c)) = iter.next() {
+        match (c, quote) {
+            ('\\', Some('"') | None) => {
+                content.push(c);
+                if let Some((_, next_c)) = iter.next() {
+                    content.push(next_c);
+                }
+                continue;
+            }
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('{', None) if content.ends_with('$') => depth += 1,
+            ('}', None) if depth == 0 => break,
+            ('}', None) => depth -= 1,
+            _ => (),
+        }
+        content.push(c);
+    }
+    content
+}
+
+/// The inside of `${...}`: `name`, `name[subscript]`, `#name` lengths, `!arr[@]` keys,
+/// `!name` indirection, `:offset:length` slices and the `-`, `=`, `+` and `?` operators,
+/// which with a `:` also treat an empty value as unset.
+fn expand_braces(content: &str, shell: &mut Shell) -> Result<Expansion, String> {
+    if let Some(rest) = content.strip_prefix('#').filter(|rest| !rest.is_empty()) {
+        let length = match split_subscript(rest) {
+            (name, Some("@" | "*")) => shell.elements(name).len(),
+            (name, Some(subscript)) => {
+                element(shell, name, subscript)?.unwrap_or_default().chars().count()
+            }
+            (name, None) => shell.get_var(name).unwrap_or_default().chars().count(),
+        };
+        return Ok(Expansion::Text(length.to_string()));
+    }
+    if let Some(rest) = content.strip_prefix('!').filter(|rest| !rest.is_empty()) {
+        return match split_subscript(rest) {
+            (name, Some(all @ ("@" | "*"))) => {
+                let keys = shell.elements(name).into_iter().map(|(key, _)| key).collect();
+                Ok(join_elements(keys, all, shell))
+            }
+            _ => match shell.get_var(rest) {
+                Some(target) if !target.is_empty() => expand_braces(&target, shell),
+                _ => Ok(Expansion::Text(String::new())),
+            },
+        };
+    }
+
+    let bad_substitution = || format!("${{{}}}: bad substitution", content);
+    let (parameter, rest) = split_parameter(content).ok_or_else(bad_substitution)?;
+    let colon = rest.starts_with(':');
+    let operation = rest.strip_prefix(':').unwrap_or(rest);
+    let Some(operator) = operation.chars().next() else {
+        return match colon {
+            true => Err(bad_substitution()),
+            false => expand_parameter(parameter, None, shell),
+        };
+    };
+    if !matches!(operator, '-' | '=' | '+' | '?') {
+        return match colon {
+            true => {
+                let slice = parse_slice(operation, shell)?;
+                expand_parameter(parameter, Some(slice), shell)
+            }
+            false => Err(bad_substitution()),
+        };
+    }
+
+    let word = &operation[1..];
+    let set = parameter_value(parameter, shell)?.is_some_and(|value| !colon || !value.is_empty());
+    match (operator, set) {
+        ('+', true) | ('-', false) => Ok(Expansion::Text(expand_word(word, shell)?)),
+        ('+', false) => Ok(Expansion::Text(String::new())),
+        ('=', false) => {
+            if !is_valid_name(split_subscript(parameter).0) {
+                return Err(format!("${}: cannot assign in this way", parameter));
+            }
+            let value = expand_word(word, shell)?;
+            shell.variables.assign(parameter, &AssignmentValue::Scalar(value))?;
+            Ok(Expansion::Text(parameter_value(parameter, shell)?.unwrap_or_default()))
+        }
+        ('?', false) => match expand_word(word, shell)? {
+            message if message.is_empty() => Err(format!("{}: parameter null or not set", parameter)),
+            message => Err(format!("{}: {}", parameter, message)),
+        },
+        _ => expand_parameter(parameter, None, shell),
+    }
+}
+
+// Splits the parameter off the front of `${...}`: a name with an optional `[subscript]`, a
+// positional parameter, or a special parameter such as `@` or `#`.
+fn split_parameter(content: &str) -> Option<(&str, &str)> {
+    let end = match content.chars().next()? {
+        c if c == '_' || c.is_ascii_alphabetic() => {
+            let end = content
+                .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+                .unwrap_or(content.len());
+            match content[end..].starts_with('[') {
+                true => end + content[end..].find(']')? + 1,
+                false => end,
+            }
+        }
+        c if c.is_ascii_digit() => {
+            content.find(|c: char| !c.is_ascii_digit()).unwrap_or(content.len())
+        }
+        '@' | '*' | '#' | '?' | '$' => 1,
+        _ => return None,
+    };
+    Some(content.split_at(end))
+}
+
+// The value a parameter expands to, sliced by `:offset:length`.
+fn expand_parameter(
+    parameter: &str,
+    slice: Option<(i64, Option<i64>)>,
+    shell: &mut Shell,
+) -> Result<Expansion, String> {
+    Ok(match split_subscript(parameter) {
+        (name, Some(all @ ("@" | "*"))) => {
+            let mut elements = shell.elements(name);
+            if let Some((offset, length)) = slice {
+                elements = slice_elements(elements, offset, length);
+            }
+            join_elements(elements.into_iter().map(|(_, value)| value).collect(), all, shell)
+        }
+        (all @ ("@" | "*"), None) => {
+            let mut elements = shell.elements(all);
+            if let Some((offset, length)) = slice {
+                // `$0` takes part in slices of the positional parameters
+                elements.insert(0, ("0".to_string(), shell.positional[0].clone()));
+                elements = slice_elements(elements, offset, length);
+            }
+            join_elements(elements.into_iter().map(|(_, value)| value).collect(), all, shell)
+        }
+        (name, subscript) => {
+            let value = match subscript {
+                Some(subscript) => element(shell, name, subscript)?.unwrap_or_default(),
+                None => shell.get_var(name).unwrap_or_default(),
+            };
+            match slice {
+                Some((offset, length)) => Expansion::Text(substring(&value, offset, length)),
+                None => Expansion::Text(value),
+            }
+        }
+    })
+}
+
+// What `-`, `=`, `+` and `?` test: the value of the parameter, or `None` when it is unset.
+// `@` and `*` are set when they have at least one element.
+fn parameter_value(parameter: &str, shell: &mut Shell) -> Result<Option<String>, String> {
+    Ok(match split_subscript(parameter) {
+        (name, Some("@" | "*")) | (name @ ("@" | "*"), None) => {
+            let values: Vec<String> = shell.elements(name).into_iter().map(|(_, value)| value).collect();
+            (!values.is_empty()).then(|| values.join(" "))
+        }
+        (name, Some(subscript)) => element(shell, name, subscript)?,
+        (name, None) => shell.get_var(name),
+    })
+}
+
+// The word of `${name:-word}` and the other operators: `~`, parameters and quotes are
+// handled as in a command word, but the result stays one string.
+fn expand_word(word: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut expanded: Option<String> = None;
+    let mut iter = word.char_indices().peekable();
+    if iter.next_if(|&(_, c)| c == '~').is_some() {
+        let mut prefix = String::new();
+        while let Some((_, c)) = iter.next_if(|&(_, c)| c.is_alphanumeric() || "+-_.".contains(c)) {
+            prefix.push(c);
+        }
+        let dir = expand_tilde(&prefix, shell).unwrap_or_else(|| format!("~{}", prefix));
+        expanded = Some(dir);
+    }
+    while let Some((_, c)) = iter.next() {
+        match c {
+            '\\' => parse_escape(&mut iter, &mut expanded, &[]),
+            '"' | '\'' => {
+                let pieces = parse_delimiter(&mut iter, c, shell)?.unwrap_or_default();
+                expanded.get_or_insert_with(String::new).push_str(&pieces.join(" "))
+            }
+            '$' => match parse_expansion(&mut iter, shell)? {
+                Some(Expansion::Text(value)) => {
+                    expanded.get_or_insert_with(String::new).push_str(&value)
+                }
+                Some(Expansion::Words(words)) => {
+                    expanded.get_or_insert_with(String::new).push_str(&words.join(" "))
+                }
+                None => add_to_token(&mut expanded, c),
+            },
+            c => add_to_token(&mut expanded, c),
+        }
+    }
+    Ok(expanded.unwrap_or_default())
+}
+
+fn element(shell: &mut Shell, name: &str, subscript: &str) -> Result<Option<String>, String> {
+    let subscript = expand_here_document(subscript, shell)?;
+    match shell.variables.get_element(name, &subscript) {
+        Ok(value) => Ok(value),
+        Err(e) => {
+            eprintln!("bash: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+// `[@]` keeps the elements apart, `[*]` joins them with the first character of IFS
+fn join_elements(values: Vec<String>, all: &str, shell: &Shell) -> Expansion {
+    if all == "@" {
+        return Expansion::Words(values);
+    }
+    let ifs = shell.get_var("IFS").unwrap_or_else(|| " ".to_string());
+    let separator = ifs.chars().next().map(String::from).unwrap_or_default();
+    Expansion::Text(values.join(&separator))
+}
+
+fn parse_slice(slice: &str, shell: &mut Shell) -> Result<(i64, Option<i64>), String> {
+    let (offset, length) = match slice.split_once(':') {
+        Some((offset, length)) => (offset, Some(length)),
+        None => (slice, None),
+    };
+    let mut evaluate = |expression: &str| {
+        let expression = expand_here_document(expression, shell)?;
+        arithmetic::evaluate(&expression, &|name| shell.get_var(name))
+    };
+    let offset = evaluate(offset)?;
+    let length = length.map(evaluate).transpose()?;
+    Ok((offset, length))
+}
+
+// Elements from index `offset` on (counting back from the end when negative), at most `length`.
+fn slice_elements(
+    elements: Vec<(String, String)>,
+    offset: i64,
+    length: Option<i64>,
+) -> Vec<(String, String)> {
+    let keys: Vec<i64> = elements.iter().map(|(key, _)| key.parse().unwrap_or(0)).collect();
+    let end = keys.last().map_or(0, |last| last + 1);
+    let offset = if offset < 0 { end + offset } else { offset };
+    let start = keys.iter().position(|key| *key >= offset).unwrap_or(elements.len());
+    let length = length.map_or(usize::MAX, |length| length.max(0) as usize);
+    elements.into_iter().skip(start).take(length).collect()
+}
+
+// `${value:offset:length}`; a negative length stops that many characters before the end
+fn substring(value: &str, offset: i64, length: Option<i64>) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len() as i64;
+    let start = if offset < 0 { (len + offset).max(0) } else { offset.min(len) };
+    let end = match length {
+        Some(length) if length < 0 => len + length,
+        Some(length) => start + length,
+        None => len,
     }
+    .clamp(start, len);
+    chars[start as usize..end as usize].iter().collect()
 }
-fn parse_delimiter(iter: &mut Peekable<CharIndices>, delimiter: char) -> Option<String> {
+
+// The contents of a quoted string. There is one piece per word: only `"$@"` and
+// `"${arr[@]}"` produce more than one.
+fn parse_delimiter(
+    iter: &mut Peekable<CharIndices>,
+    delimiter: char,
+    shell: &mut Shell,
+) -> Result<Option<Vec<String>>, String> {
+    let mut pieces: Vec<String> = Vec::new();
     let mut token: Option<String> = None;
     let is_double_quote = delimiter == '"';
     let escaped_chars = vec!['"', '\\', '$', '`', '\n'];
@@ -70,20 +587,66 @@ fn parse_delimiter(iter: &mut Peekable<CharIndices>, delimiter: char) -> Option<
             parse_escape(iter, &mut token, &escaped_chars);
             continue;
         }
-        if c == delimiter {
-            if let Some(&(_, next_c)) = iter.peek() {
-                if next_c == delimiter {
-                    iter.next();
-                    continue;
+        if is_double_quote && c == '$' {
+            match parse_expansion(iter, shell)? {
+                Some(Expansion::Text(value)) => token.get_or_insert_with(String::new).push_str(&value),
+                Some(Expansion::Words(words)) => {
+                    for (i, word) in words.into_iter().enumerate() {
+                        if i > 0 {
+                            pieces.push(token.take().unwrap_or_default());
+                        }
+                        token.get_or_insert_with(String::new).push_str(&word);
+                    }
                 }
+                None => add_to_token(&mut token, c),
+            }
+            continue;
+        }
+        if c == delimiter {
+            if let Some(&(_, next_c)) = iter.peek()
+                && next_c == delimiter
+            {
+                iter.next();
+                continue;
             }
-            if token.is_some() {
-                return token;
+            if let Some(token) = token {
+                pieces.push(token);
+            }
+            if !pieces.is_empty() {
+                return Ok(Some(pieces));
             }
 
-            return None;
+            return Ok(None);
         }
         add_to_token(&mut token, c);
     }
-    None
+    Ok(None)
+}
+
+/// Expands the body of an unquoted here-document: parameters are expanded but quotes are
+/// literal, and a backslash only escapes `$`, `` ` ``, `\` and newline.
+pub fn expand_here_document(body: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut expanded: Option<String> = None;
+    let mut iter = body.char_indices().peekable();
+    while let Some((_, c)) = iter.next() {
+        match c {
+            '\\' => match iter.peek() {
+                Some(&(_, '\n')) => {
+                    iter.next();
+                }
+                _ => parse_escape(&mut iter, &mut expanded, &['$', '`', '\\']),
+            },
+            '$' => match parse_expansion(&mut iter, shell)? {
+                Some(Expansion::Text(value)) => {
+                    expanded.get_or_insert_with(String::new).push_str(&value)
+                }
+                Some(Expansion::Words(words)) => {
+                    expanded.get_or_insert_with(String::new).push_str(&words.join(" "))
+                }
+                None => add_to_token(&mut expanded, c),
+            },
+            c => add_to_token(&mut expanded, c),
+        }
+    }
+    Ok(expanded.unwrap_or_default())
 }
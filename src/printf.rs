@@ -0,0 +1,559 @@
+use std::ffi::CString;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Formats `args` with a `printf` format string. The format is reused while arguments
+/// remain; missing arguments count as empty strings or zero. Returns the output and the
+/// error messages, such as for arguments that aren't valid numbers.
+pub fn printf(format: &str, args: &[String]) -> (String, Vec<String>) {
+    let mut formatter = Formatter {
+        args,
+        next: 0,
+        output: String::new(),
+        errors: Vec::new(),
+    };
+    loop {
+        let start = formatter.next;
+        if !formatter.format_once(format) {
+            break;
+        }
+        // a format that consumes no arguments is only printed once
+        if formatter.next == start || formatter.next >= args.len() {
+            break;
+        }
+    }
+    (formatter.output, formatter.errors)
+}
+
+/// Interprets the backslash escapes of `echo -e` and `printf %b`: `\a \b \e \f \n \r \t \v
+/// \\`, `\0nnn` octal, `\xHH`, `\uHHHH` and `\UHHHHHHHH`. `bare_octal` also takes `\nnn`
+/// without the leading zero, as `%b` does. Returns `false` as the second value when `\c` cut
+/// the output short.
+pub fn interpret_escapes(text: &str, bare_octal: bool) -> (String, bool) {
+    let mut chars = text.chars().peekable();
+    let mut output = String::new();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match escape(&mut chars, true, bare_octal) {
+            Some(text) => output.push_str(&text),
+            None => return (output, false),
+        }
+    }
+    (output, true)
+}
+
+/// `value` quoted so the shell reads it back as one word, as `printf %q` does.
+pub fn shell_quote(value: &str) -> String {
+    if value.is_empty() {
+        return "''".to_string();
+    }
+    if value.chars().any(|c| c.is_control()) {
+        let mut quoted = String::from("$'");
+        for c in value.chars() {
+            match c {
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                '\x1b' => quoted.push_str("\\E"),
+                '\'' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                c if c.is_control() => quoted.push_str(&format!("\\{:03o}", c as u32)),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('\'');
+        return quoted;
+    }
+    let mut quoted = String::new();
+    for (i, c) in value.chars().enumerate() {
+        let plain =
+            c.is_alphanumeric() || "_./,:@%+=-".contains(c) || (i > 0 && "~#".contains(c));
+        if !plain {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+struct Formatter<'a> {
+    args: &'a [String],
+    next: usize,
+    output: String,
+    errors: Vec<String>,
+}
+
+// a conversion's flags, width and precision
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Formatter<'_> {
+    // one pass over the format; `false` when output must stop
+    fn format_once(&mut self, format: &str) -> bool {
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let text = escape(&mut chars, false, true).unwrap_or_default();
+                    self.output.push_str(&text);
+                }
+                '%' if chars.next_if_eq(&'%').is_some() => self.output.push('%'),
+                '%' => {
+                    if !self.conversion(&mut chars) {
+                        return false;
+                    }
+                }
+                c => self.output.push(c),
+            }
+        }
+        true
+    }
+
+    fn conversion(&mut self, chars: &mut Peekable<Chars>) -> bool {
+        let mut spec = Spec::default();
+        while let Some(flag) = chars.next_if(|c| "-+ #0'".contains(*c)) {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero = true,
+                _ => {}
+            }
+        }
+        if chars.next_if_eq(&'*').is_some() {
+            let width = self.next_integer();
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+        } else {
+            spec.width = digits(chars);
+        }
+        if chars.next_if_eq(&'.').is_some() {
+            spec.precision = match chars.next_if_eq(&'*') {
+                Some(_) => usize::try_from(self.next_integer()).ok(),
+                None => Some(digits(chars)),
+            };
+        }
+        // length modifiers mean nothing here
+        while chars.next_if(|c| "hlLjzt".contains(*c)).is_some() {}
+
+        let Some(conversion) = chars.next() else {
+            self.errors.push("`%': missing format character".to_string());
+            return false;
+        };
+        let (sign, body) = match conversion {
+            's' => (String::new(), truncate(self.next_argument(), spec.precision)),
+            'q' => (String::new(), truncate(&shell_quote(self.next_argument()), spec.precision)),
+            'c' => (String::new(), self.next_argument().chars().take(1).collect()),
+            'b' => {
+                let (text, complete) = interpret_escapes(self.next_argument(), true);
+                let text = truncate(&text, spec.precision);
+                if !complete {
+                    self.output.push_str(&pad("", &text, &spec, false));
+                    return false;
+                }
+                (String::new(), text)
+            }
+            'd' | 'i' => {
+                let value = self.next_integer();
+                let digits = minimum_digits(value.unsigned_abs().to_string(), spec.precision);
+                (sign(value < 0, &spec), digits)
+            }
+            'u' | 'x' | 'X' | 'o' => {
+                let value = self.next_integer() as u64;
+                let digits = match conversion {
+                    'u' => value.to_string(),
+                    'x' => format!("{:x}", value),
+                    'X' => format!("{:X}", value),
+                    _ => format!("{:o}", value),
+                };
+                let digits = minimum_digits(digits, spec.precision);
+                let prefix = match (spec.alternate && value != 0, conversion) {
+                    (true, 'x') => "0x",
+                    (true, 'X') => "0X",
+                    (true, 'o') if !digits.starts_with('0') => "0",
+                    _ => "",
+                };
+                (prefix.to_string(), digits)
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = self.next_float();
+                let precision = spec.precision.unwrap_or(6);
+                let body = format_float(value.abs(), conversion, precision, spec.alternate);
+                (sign(value.is_sign_negative() && value != 0.0, &spec), body)
+            }
+            '(' => {
+                let mut time_format = String::new();
+                for c in chars.by_ref() {
+                    if c == ')' {
+                        break;
+                    }
+                    time_format.push(c);
+                }
+                if chars.next_if_eq(&'T').is_none() {
+                    self.errors.push("`(': invalid format character".to_string());
+                    return false;
+                }
+                let time = match self.args.get(self.next).filter(|arg| !arg.is_empty()) {
+                    Some(_) => self.next_integer(),
+                    None => {
+                        self.next += 1;
+                        -1
+                    }
+                };
+                (String::new(), truncate(&format_time(&time_format, time), spec.precision))
+            }
+            c => {
+                self.errors.push(format!("`{}': invalid format character", c));
+                return false;
+            }
+        };
+        // `0` pads numbers, except integers given a precision
+        let zeros = spec.zero
+            && match conversion {
+                'd' | 'i' | 'u' | 'x' | 'X' | 'o' => spec.precision.is_none(),
+                conversion => "fFeEgG".contains(conversion),
+            };
+        self.output.push_str(&pad(&sign, &body, &spec, zeros));
+        true
+    }
+
+    fn next_argument(&mut self) -> &str {
+        let arg = self.args.get(self.next).map(String::as_str).unwrap_or_default();
+        self.next += 1;
+        arg
+    }
+
+    fn next_integer(&mut self) -> i64 {
+        let arg = self.next_argument().to_string();
+        let (value, valid) = parse_integer(&arg);
+        if !valid {
+            self.errors.push(format!("{}: invalid number", arg));
+        }
+        value
+    }
+
+    fn next_float(&mut self) -> f64 {
+        let arg = self.next_argument().to_string();
+        let trimmed = arg.trim();
+        if let Ok(value) = trimmed.parse::<f64>() {
+            return value;
+        }
+        let (value, valid) = parse_integer(&arg);
+        if !valid {
+            self.errors.push(format!("{}: invalid number", arg));
+        }
+        value as f64
+    }
+}
+
+// one backslash escape, the backslash already consumed. `echo` style takes octal as `\0nnn`
+// and stops at `\c`; the format string takes `\nnn` and leaves `\c` alone. `bare_octal` adds
+// `\nnn` to the `echo` style.
+fn escape(chars: &mut Peekable<Chars>, echo: bool, bare_octal: bool) -> Option<String> {
+    let Some(c) = chars.next() else {
+        return Some("\\".to_string());
+    };
+    let text = match c {
+        'a' => '\x07',
+        'b' => '\x08',
+        'e' | 'E' => '\x1b',
+        'f' => '\x0c',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'v' => '\x0b',
+        '\\' => '\\',
+        'c' if echo => return None,
+        '0' if echo => from_digits(chars, 8, 3).unwrap_or('\0'),
+        '0'..='7' if !echo || bare_octal => {
+            let mut digits = c.to_string();
+            while digits.len() < 3
+                && let Some(digit) = chars.next_if(|c| c.is_digit(8))
+            {
+                digits.push(digit);
+            }
+            char::from_u32(u32::from_str_radix(&digits, 8).unwrap_or(0)).unwrap_or('\0')
+        }
+        'x' => match from_digits(chars, 16, 2) {
+            Some(c) => c,
+            None => return Some("\\x".to_string()),
+        },
+        'u' => match from_digits(chars, 16, 4) {
+            Some(c) => c,
+            None => return Some("\\u".to_string()),
+        },
+        'U' => match from_digits(chars, 16, 8) {
+            Some(c) => c,
+            None => return Some("\\U".to_string()),
+        },
+        '"' | '\'' if !echo => c,
+        c => return Some(format!("\\{}", c)),
+    };
+    Some(text.to_string())
+}
+
+// up to `max` digits in `radix`, as a character
+fn from_digits(chars: &mut Peekable<Chars>, radix: u32, max: usize) -> Option<char> {
+    let mut digits = String::new();
+    while digits.len() < max
+        && let Some(c) = chars.next_if(|c| c.is_digit(radix))
+    {
+        digits.push(c);
+    }
+    let value = u32::from_str_radix(&digits, radix).ok()?;
+    Some(char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER))
+}
+
+fn digits(chars: &mut Peekable<Chars>) -> usize {
+    let mut value = 0usize;
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        value = value.saturating_mul(10).saturating_add(c.to_digit(10).unwrap_or(0) as usize);
+    }
+    value
+}
+
+/// A `printf` numeric argument: decimal, `0x` hex, leading-zero octal, or `'c` for the code
+/// of `c`. The second value is `false` when the argument isn't entirely a number; the first
+/// is then whatever leading part parsed.
+pub fn parse_integer(arg: &str) -> (i64, bool) {
+    let text = arg.trim_start();
+    if let Some(quoted) = text.strip_prefix(['\'', '"']) {
+        return (quoted.chars().next().map(|c| c as i64).unwrap_or(0), true);
+    }
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (radix, text) = if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        (16, hex)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    let end = text.find(|c: char| !c.is_digit(radix)).unwrap_or(text.len());
+    let value = match end {
+        0 => 0,
+        _ => i64::from_str_radix(&text[..end], radix).unwrap_or(i64::MAX),
+    };
+    let value = if negative { value.wrapping_neg() } else { value };
+    let valid = arg.trim().is_empty() || end > 0 && text[end..].trim().is_empty();
+    (value, valid)
+}
+
+fn sign(negative: bool, spec: &Spec) -> String {
+    match (negative, spec.plus, spec.space) {
+        (true, _, _) => "-",
+        (false, true, _) => "+",
+        (false, false, true) => " ",
+        _ => "",
+    }
+    .to_string()
+}
+
+fn minimum_digits(digits: String, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) if digits.len() < precision => {
+            format!("{}{}", "0".repeat(precision - digits.len()), digits)
+        }
+        _ => digits,
+    }
+}
+
+fn truncate(text: &str, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text.to_string(),
+    }
+}
+
+// pads to the field width; zeros go between the sign or prefix and the digits
+fn pad(sign: &str, body: &str, spec: &Spec, zeros: bool) -> String {
+    let len = sign.chars().count() + body.chars().count();
+    let fill = spec.width.saturating_sub(len);
+    if spec.left {
+        format!("{}{}{}", sign, body, " ".repeat(fill))
+    } else if zeros {
+        format!("{}{}{}", sign, "0".repeat(fill), body)
+    } else {
+        format!("{}{}{}", " ".repeat(fill), sign, body)
+    }
+}
+
+// `%f`, `%e` and `%g` of a non-negative `value`, as C's printf writes them
+fn format_float(value: f64, conversion: char, precision: usize, alternate: bool) -> String {
+    let upper = conversion.is_ascii_uppercase();
+    if !value.is_finite() {
+        let text = if value.is_nan() { "nan" } else { "inf" };
+        return if upper { text.to_uppercase() } else { text.to_string() };
+    }
+    match conversion.to_ascii_lowercase() {
+        'f' => format!("{:.*}", precision, value),
+        'e' => exponential(value, precision, upper),
+        _ => {
+            let precision = precision.max(1);
+            let exponent = exponent_of(value, precision - 1);
+            let text = if exponent < -4 || exponent >= precision as i32 {
+                exponential(value, precision - 1, upper)
+            } else {
+                format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+            };
+            match alternate {
+                true => text,
+                false => strip_fraction_zeros(&text),
+            }
+        }
+    }
+}
+
+fn exponential(value: f64, precision: usize, upper: bool) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let e = if upper { 'E' } else { 'e' };
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}{}{}{:02}", mantissa, e, sign, exponent.abs())
+}
+
+// the decimal exponent of `value` once rounded to `precision` fraction digits
+fn exponent_of(value: f64, precision: usize) -> i32 {
+    let text = format!("{:.*e}", precision, value);
+    text.split_once('e').and_then(|(_, e)| e.parse().ok()).unwrap_or(0)
+}
+
+// `1.500000` to `1.5`, `2.000e+00` to `2e+00`
+fn strip_fraction_zeros(text: &str) -> String {
+    let (number, exponent) = match text.find(['e', 'E']) {
+        Some(index) => text.split_at(index),
+        None => (text, ""),
+    };
+    let number = match number.contains('.') {
+        true => number.trim_end_matches('0').trim_end_matches('.'),
+        false => number,
+    };
+    format!("{}{}", number, exponent)
+}
+
+/// `%(format)T`: `time` seconds since the epoch, or now when it is -1 or -2, formatted with
+/// strftime in local time. An empty format means `%X`.
+fn format_time(format: &str, time: i64) -> String {
+    let time = match time {
+        -1 | -2 => unsafe { libc::time(std::ptr::null_mut()) },
+        time => time as libc::time_t,
+    };
+    let format = if format.is_empty() { "%X" } else { format };
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return String::new();
+    }
+    let mut buffer = vec![0u8; 256];
+    let len = unsafe {
+        let buffer_ptr = buffer.as_mut_ptr() as *mut libc::c_char;
+        libc::strftime(buffer_ptr, buffer.len(), format.as_ptr(), &tm)
+    };
+    buffer.truncate(len);
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(format: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let (output, errors) = printf(format, &args);
+        assert_eq!(errors, Vec::<String>::new());
+        output
+    }
+
+    #[test]
+    fn integer_conversions() {
+        assert_eq!(format("%d|%i", &["42", "-7"]), "42|-7");
+        assert_eq!(format("%5d|%-5d|%05d", &["42", "42", "42"]), "   42|42   |00042");
+        assert_eq!(format("%+d|% d", &["42", "42"]), "+42| 42");
+        assert_eq!(format("%.3d", &["7"]), "007");
+        assert_eq!(format("%x|%X|%#x", &["255", "255", "255"]), "ff|FF|0xff");
+        assert_eq!(format("%o|%#o", &["8", "8"]), "10|010");
+        assert_eq!(format("%d|%d", &["0x1f", "010"]), "31|8");
+        assert_eq!(format("%d", &["'A"]), "65");
+    }
+
+    #[test]
+    fn string_conversions() {
+        assert_eq!(format("%s|%5s|%-5s", &["abc", "abc", "abc"]), "abc|  abc|abc  ");
+        assert_eq!(format("%.2s|%5.1s", &["abc", "abc"]), "ab|    a");
+        assert_eq!(format("%c", &["hello"]), "h");
+        assert_eq!(format("%b", &["a\\tb\\\\n"]), "a\tb\\n");
+        assert_eq!(format("%q", &["a b"]), "a\\ b");
+        assert_eq!(format("%%|%s", &[]), "%|");
+    }
+
+    #[test]
+    fn width_and_precision_from_arguments() {
+        assert_eq!(format("%*d|%.*f", &["4", "7", "1", "2.25"]), "   7|2.2");
+        assert_eq!(format("%-*s|", &["4", "ab"]), "ab  |");
+    }
+
+    #[test]
+    fn float_conversions() {
+        assert_eq!(format("%f|%.2f", &["3.14159", "3.14159"]), "3.141590|3.14");
+        assert_eq!(format("%8.3f|%-8.1f|", &["3.14159", "3.14159"]), "   3.142|3.1     |");
+        assert_eq!(format("%e|%.2E", &["1234.5", "0.000123"]), "1.234500e+03|1.23E-04");
+    }
+
+    #[test]
+    fn general_float_conversion() {
+        assert_eq!(format("%g|%g", &["100000", "1000000"]), "100000|1e+06");
+        assert_eq!(format("%g|%g", &["0.0001", "0.00001"]), "0.0001|1e-05");
+        assert_eq!(format("%g|%g|%g", &["0", "123456789", "-0.5"]), "0|1.23457e+08|-0.5");
+        assert_eq!(format("%.3g|%#g|%G", &["3.14159", "1.5", "1e-10"]), "3.14|1.50000|1E-10");
+        assert_eq!(format("%10.4g|%-10g|", &["3.14159", "2.5"]), "     3.142|2.5       |");
+    }
+
+    #[test]
+    fn format_is_reused_for_remaining_arguments() {
+        assert_eq!(format("[%s %s]", &["a", "b", "c"]), "[a b][c ]");
+        assert_eq!(format("x\\n", &["ignored"]), "x\n");
+        assert_eq!(format("%s-%d,", &[]), "-0,");
+    }
+
+    #[test]
+    fn invalid_numbers_are_reported() {
+        let (output, errors) = printf("%d|%d", &["abc".to_string(), "12x".to_string()]);
+        assert_eq!(output, "0|12");
+        assert_eq!(errors, vec!["abc: invalid number", "12x: invalid number"]);
+    }
+
+    #[test]
+    fn escapes() {
+        // like `echo -e`, octal takes a leading zero
+        assert_eq!(
+            interpret_escapes("a\\x41\\101\\0101\\u00e9", false),
+            ("aA\\101A\u{e9}".to_string(), true)
+        );
+        assert_eq!(interpret_escapes("ab\\cde", false), ("ab".to_string(), false));
+    }
+
+    #[test]
+    fn b_conversion_takes_octal_with_or_without_a_zero() {
+        assert_eq!(format("%b|", &["\\101", "\\0101", "\\1", "\\08"]), "A|A|\u{1}|\u{0}8|");
+        assert_eq!(format("%b", &["a\\cb"]), "a");
+    }
+}
@@ -1,12 +1,34 @@
-use std::fs::OpenOptions;
-#[derive(Debug)]
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::execute::Input;
+use crate::parser::{expand_here_document, Word};
+use crate::shell::Shell;
+use crate::utils::io_error_message;
 
-pub struct Redirection {
-    pub options: OpenOptions,
-    pub filename: Option<String>,
+/// One step of a command's redirections. They are applied in order, so `>file 2>&1` and
+/// `2>&1 >file` differ the way they do in POSIX shells.
+#[derive(Debug)]
+pub enum Redirection {
+    Open {
+        fd: RawFd,
+        filename: String,
+        options: OpenOptions,
+    },
+    // `>` under noclobber: like `Open`, but never truncates an existing regular file
+    Create { fd: RawFd, filename: String },
+    // the body of a here-document or here-string
+    Text { fd: RawFd, text: String },
+    Duplicate { fd: RawFd, source: RawFd },
+    Close { fd: RawFd },
 }
 impl Redirection {
-     fn new(overwrite: bool, filename: Option<String>) -> Redirection {
+     fn new(fd: RawFd, overwrite: bool, filename: String) -> Redirection {
         let file_options = OpenOptions::new()
             .create(true)
             .write(true)
@@ -14,41 +36,456 @@ impl Redirection {
             .append(!overwrite)
             .clone();
 
-        Redirection {
+        Redirection::Open {
+            fd,
             options: file_options,
             filename,
         }
     }
-   pub fn parse_redirections(tokens: &mut Vec<String>) -> (Option<Redirection>, Option<Redirection>) {
-        let mut std_out_file = None;
-        let mut std_err_file = None;
-        while let Some(index) = tokens.iter().position(|t| {
-            t == ">" || t == "1>" || t == ">>" || t == "1>>" || t == "2>" || t == "2>>"
-        }) {
-            let mut file_name = None;
-            let operator = tokens.remove(index);
-            match index + 1 <= tokens.len() {
-                true => file_name = Some(tokens.remove(index)),
-                false => (),
+
+    fn input(fd: RawFd, filename: String) -> Redirection {
+        Redirection::Open {
+            fd,
+            options: OpenOptions::new().read(true).clone(),
+            filename,
+        }
+    }
+
+    fn truncate(fd: RawFd, filename: String, noclobber: bool) -> Redirection {
+        match noclobber {
+            true => Redirection::Create { fd, filename },
+            false => Redirection::new(fd, true, filename),
+        }
+    }
+
+    // `n>&m`, `n<&m` and `n>&-`; bash treats `>&word` with a non-numeric word as `&>word`
+    fn duplicate(fd: RawFd, target: String, noclobber: bool) -> Vec<Redirection> {
+        if target == "-" {
+            return vec![Redirection::Close { fd }];
+        }
+        match target.parse() {
+            Ok(source) => vec![Redirection::Duplicate { fd, source }],
+            Err(_) => vec![
+                Redirection::truncate(1, target, noclobber),
+                Redirection::Duplicate { fd: 2, source: 1 },
+            ],
+        }
+    }
+
+   pub fn parse_redirections(
+        tokens: &mut Vec<Word>,
+        here_documents: &mut VecDeque<String>,
+        noclobber: bool,
+    ) -> Result<Vec<Redirection>, String> {
+        let mut redirections = Vec::new();
+        let mut index = 0;
+        while index < tokens.len() {
+            // a quoted or escaped `>` is just text
+            let operator = match tokens[index].is_literal() {
+                true => split_operator(&tokens[index].text),
+                false => None,
+            };
+            let Some((fd, operator, word)) = operator else {
+                index += 1;
+                continue;
+            };
+            let (operator, mut word) = (operator.to_string(), word.to_string());
+            tokens.remove(index);
+            if word.is_empty() {
+                if index == tokens.len() {
+                    return Err("syntax error near unexpected token `newline'".to_string());
+                }
+                word = tokens.remove(index).text;
             }
 
+            let input_fd = fd.unwrap_or(0);
+            let output_fd = fd.unwrap_or(1);
             match operator.as_str() {
-                ">" | "1>" => {
-                    std_out_file = Some(Redirection::new(true, file_name));
+                "<" => redirections.push(Redirection::input(input_fd, word)),
+                "<>" => redirections.push(Redirection::Open {
+                    fd: input_fd,
+                    options: OpenOptions::new().read(true).write(true).create(true).clone(),
+                    filename: word,
+                }),
+                ">" => redirections.push(Redirection::truncate(output_fd, word, noclobber)),
+                ">|" => redirections.push(Redirection::new(output_fd, true, word)),
+                ">>" => redirections.push(Redirection::new(output_fd, false, word)),
+                "&>" => {
+                    redirections.push(Redirection::truncate(1, word, noclobber));
+                    redirections.push(Redirection::Duplicate { fd: 2, source: 1 });
+                }
+                "&>>" => {
+                    redirections.push(Redirection::new(1, false, word));
+                    redirections.push(Redirection::Duplicate { fd: 2, source: 1 });
+                }
+                ">&" => redirections.extend(Redirection::duplicate(output_fd, word, noclobber)),
+                "<&" => redirections.extend(Redirection::duplicate(input_fd, word, noclobber)),
+                "<<<" => redirections.push(Redirection::Text {
+                    fd: input_fd,
+                    text: word + "\n",
+                }),
+                // "<<" and "<<-": the body was collected along with the command line
+                _ => redirections.push(Redirection::Text {
+                    fd: input_fd,
+                    text: here_documents.pop_front().unwrap_or_default(),
+                }),
+            }
+        }
+
+        Ok(redirections)
+    }
+}
+
+const OPERATORS: [&str; 12] =
+    ["&>>", "&>", "<<<", "<<-", "<<", "<>", ">>", ">&", ">|", "<&", ">", "<"];
+
+/// The redirection operator `text` starts with, if any.
+pub fn operator_at(text: &str) -> Option<&'static str> {
+    OPERATORS.iter().find(|op| text.starts_with(**op)).copied()
+}
+
+/// Splits a token such as `2>&1`, `>>log` or `<<EOF` into its fd, operator and word.
+fn split_operator(token: &str) -> Option<(Option<RawFd>, &'static str, &str)> {
+    let digits = token.len() - token.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = &token[digits..];
+    let operator = operator_at(rest)?;
+    if digits > 0 && operator.starts_with('&') {
+        return None;
+    }
+    let fd = match digits {
+        0 => None,
+        _ => Some(token[..digits].parse().ok()?),
+    };
+    Some((fd, operator, &rest[operator.len()..]))
+}
+
+/// Whether `word` is a redirection operator waiting for its target, such as `2>` or `<<`.
+pub fn is_operator(word: &Word) -> bool {
+    word.is_literal() && split_operator(&word.text).is_some_and(|(_, _, rest)| rest.is_empty())
+}
+
+pub fn is_here_document_operator(token: &Word) -> bool {
+    token.is_literal() && matches!(split_operator(&token.text), Some((_, "<<" | "<<-", _)))
+}
+
+/// Where the fds of a command point once its redirections have been applied. Fds missing
+/// from the table are inherited from the shell, `None` marks a closed fd.
+#[derive(Debug, Default)]
+pub struct FdTable {
+    fds: BTreeMap<RawFd, Option<OwnedFd>>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        FdTable::default()
+    }
+
+    pub fn try_clone(&self) -> io::Result<FdTable> {
+        let mut fds = BTreeMap::new();
+        for (fd, file) in &self.fds {
+            let file = match file {
+                Some(file) => Some(dup_high(file.as_raw_fd())?),
+                None => None,
+            };
+            fds.insert(*fd, file);
+        }
+        Ok(FdTable { fds })
+    }
+
+    pub fn set(&mut self, fd: RawFd, file: impl Into<OwnedFd>) -> io::Result<()> {
+        // keep our copies above the fds users normally redirect
+        let file = dup_high(file.into().as_raw_fd())?;
+        self.fds.insert(fd, Some(file));
+        Ok(())
+    }
+
+    pub fn apply(&mut self, redirections: &[Redirection]) -> Result<(), String> {
+        for redirection in redirections {
+            match redirection {
+                Redirection::Open {
+                    fd,
+                    filename,
+                    options,
+                } => {
+                    let file = options
+                        .open(filename)
+                        .map_err(|e| format!("{}: {}", filename, io_error_message(&e)))?;
+                    self.set(*fd, file).map_err(|e| io_error_message(&e))?;
+                }
+                Redirection::Create { fd, filename } => {
+                    let file = create_file(filename)
+                        .map_err(|e| format!("{}: {}", filename, io_error_message(&e)))?;
+                    self.set(*fd, file).map_err(|e| io_error_message(&e))?;
+                }
+                Redirection::Text { fd, text } => {
+                    let file = here_document_file(text)
+                        .map_err(|e| format!("here-document: {}", io_error_message(&e)))?;
+                    self.set(*fd, file).map_err(|e| io_error_message(&e))?;
+                }
+                Redirection::Duplicate { fd, source } => {
+                    let file = self
+                        .duplicate(*source)
+                        .map_err(|_| format!("{}: Bad file descriptor", source))?;
+                    self.fds.insert(*fd, Some(file));
+                }
+                Redirection::Close { fd } => {
+                    self.fds.insert(*fd, None);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A readable handle on `fd` as seen through this table, for `read -u` and friends.
+    pub fn reader(&self, fd: RawFd) -> io::Result<File> {
+        self.duplicate(fd).map(File::from)
+    }
+
+    fn duplicate(&self, fd: RawFd) -> io::Result<OwnedFd> {
+        match self.fds.get(&fd) {
+            Some(Some(file)) => dup_high(file.as_raw_fd()),
+            Some(None) => Err(io::Error::from_raw_os_error(libc::EBADF)),
+            None => dup_high(fd),
+        }
+    }
+
+    pub fn input(&self) -> Box<dyn Input> {
+        match self.fds.get(&0) {
+            Some(Some(file)) => match dup_high(file.as_raw_fd()) {
+                Ok(file) => Box::new(File::from(file)),
+                Err(_) => Box::new(ClosedFd),
+            },
+            Some(None) => Box::new(ClosedFd),
+            None => Box::new(RawStdin),
+        }
+    }
+
+    pub fn output(&self, fd: RawFd) -> Box<dyn Write> {
+        match self.fds.get(&fd) {
+            Some(Some(file)) => match dup_high(file.as_raw_fd()) {
+                Ok(file) => Box::new(File::from(file)),
+                Err(_) => Box::new(ClosedFd),
+            },
+            Some(None) => Box::new(ClosedFd),
+            None if fd == 2 => Box::new(io::stderr()),
+            None => Box::new(io::stdout()),
+        }
+    }
+
+    /// `(fd, source)` pairs to install in a child before exec; a `None` source closes the fd.
+    pub fn child_fds(&self) -> Vec<(RawFd, Option<RawFd>)> {
+        self.fds
+            .iter()
+            .map(|(fd, file)| (*fd, file.as_ref().map(|f| f.as_raw_fd())))
+            .collect()
+    }
+}
+
+/// Opens `filename` for `>` under noclobber. `create_new` makes the existence check and the
+/// creation one step; existing files are only reused when they aren't regular, like /dev/null.
+fn create_file(filename: &str) -> io::Result<File> {
+    match OpenOptions::new().write(true).create_new(true).open(filename) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            let file = OpenOptions::new().write(true).open(filename)?;
+            match file.metadata()?.is_file() {
+                true => Err(io::Error::other("cannot overwrite existing file")),
+                false => Ok(file),
+            }
+        }
+        result => result,
+    }
+}
+
+/// Installs the fds from [`FdTable::child_fds`]. Only async-signal-safe calls, as this runs
+/// between fork and exec.
+pub fn install_child_fds(fds: &[(RawFd, Option<RawFd>)]) -> io::Result<()> {
+    for &(fd, source) in fds {
+        let result = match source {
+            Some(source) => unsafe { libc::dup2(source, fd) },
+            None => unsafe { libc::close(fd) },
+        };
+        if result == -1 && source.is_some() {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+const SHELL_FD_BASE: RawFd = 10;
+
+fn dup_high(fd: RawFd) -> io::Result<OwnedFd> {
+    let new_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, SHELL_FD_BASE) };
+    if new_fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(new_fd) })
+}
+
+/// Stands in for a closed fd so builtins get `Bad file descriptor` on use.
+struct ClosedFd;
+
+impl io::Read for ClosedFd {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(libc::EBADF))
+    }
+}
+
+impl io::Write for ClosedFd {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(libc::EBADF))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for ClosedFd {
+    fn as_raw_fd(&self) -> RawFd {
+        -1
+    }
+}
+
+/// The shell's own stdin, read without a buffer. Whatever a builtin doesn't consume is left
+/// for the commands read after it, and polling the fd for `read -t` sees all pending input.
+struct RawStdin;
+
+impl io::Read for RawStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match unsafe { libc::read(0, buf.as_mut_ptr().cast(), buf.len()) } {
+            -1 => Err(io::Error::last_os_error()),
+            len => Ok(len as usize),
+        }
+    }
+}
+
+impl AsRawFd for RawStdin {
+    fn as_raw_fd(&self) -> RawFd {
+        0
+    }
+}
+
+// Like bash, bodies go through an unlinked temporary file so they survive a fork and
+// never block on a full pipe.
+fn here_document_file(text: &str) -> io::Result<File> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!(
+        "sh-heredoc-{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+    file.write_all(text.as_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+#[derive(Debug)]
+pub struct HereDocument {
+    pub delimiter: String,
+    pub quoted: bool,
+    pub strip_tabs: bool,
+}
+
+impl HereDocument {
+    pub fn is_delimiter(&self, line: &str) -> bool {
+        match self.strip_tabs {
+            true => line.trim_start_matches('\t') == self.delimiter,
+            false => line == self.delimiter,
+        }
+    }
+}
+
+/// Finds the `<<word` and `<<-word` operators of a command line, skipping quoted text.
+pub fn find_here_documents(line: &str) -> Vec<HereDocument> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut here_documents = Vec::new();
+    let mut quote = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == '\\' => i += 1,
+            Some(_) => (),
+            None if c == '\\' => i += 1,
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '<' && chars.get(i) == Some(&'<') => {
+                i += 1;
+                if chars.get(i) == Some(&'<') {
+                    i += 1;
+                    continue;
                 }
-                ">>" | "1>>" => {
-                    std_out_file = Some(Redirection::new(false, file_name));
+                let strip_tabs = chars.get(i) == Some(&'-');
+                if strip_tabs {
+                    i += 1;
                 }
-                "2>" => {
-                    std_err_file = Some(Redirection::new(true, file_name));
+                while chars.get(i) == Some(&' ') {
+                    i += 1;
                 }
-                "2>>" => {
-                    std_err_file = Some(Redirection::new(false, file_name));
+                let mut delimiter = String::new();
+                let mut quoted = false;
+                let mut word_quote = None;
+                while let Some(&c) = chars.get(i) {
+                    match word_quote {
+                        Some(q) if c == q => word_quote = None,
+                        Some(_) => delimiter.push(c),
+                        None if c == ' ' || "|&;<>".contains(c) => break,
+                        None if c == '\'' || c == '"' => {
+                            quoted = true;
+                            word_quote = Some(c);
+                        }
+                        None if c == '\\' => {
+                            quoted = true;
+                            i += 1;
+                            delimiter.extend(chars.get(i));
+                        }
+                        None => delimiter.push(c),
+                    }
+                    i += 1;
                 }
-                _ => {}
+                here_documents.push(HereDocument {
+                    delimiter,
+                    quoted,
+                    strip_tabs,
+                });
             }
+            None => (),
         }
+    }
+    here_documents
+}
 
-        (std_out_file, std_err_file)
+/// Collects the bodies of the here-documents opened on `line` from the lines that follow it.
+pub fn read_here_documents(
+    line: &str,
+    rest: &str,
+    shell: &mut Shell,
+) -> Result<VecDeque<String>, String> {
+    let mut lines = rest.lines();
+    let mut bodies = VecDeque::new();
+    for here_document in find_here_documents(line) {
+        let mut body = String::new();
+        for line in lines.by_ref() {
+            if here_document.is_delimiter(line) {
+                break;
+            }
+            match here_document.strip_tabs {
+                true => body.push_str(line.trim_start_matches('\t')),
+                false => body.push_str(line),
+            }
+            body.push('\n');
+        }
+        if !here_document.quoted {
+            body = expand_here_document(&body, shell)?;
+        }
+        bodies.push_back(body);
     }
+    Ok(bodies)
 }
\ No newline at end of file
@@ -1,76 +1,484 @@
-use std::{env, path::PathBuf};
-use std::path::Path;
-use is_executable::is_executable;
-use rustyline::{config::Configurer, history::FileHistory, CompletionType, Editor};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::{env, fs, io, mem, path::{Path, PathBuf}};
+use rustyline::error::ReadlineError;
+use rustyline::{config::Configurer, history::FileHistory, CompletionType, Config, Editor};
 use rustyline::history::History;
+use crate::aliases::Aliases;
+use crate::command_hash::CommandHash;
+use crate::builtin::normalize_path;
 use crate::completion_helper::MyHelper;
+use crate::frecency::Frecency;
+use crate::redirection::{find_here_documents, FdTable};
+use crate::signals;
+use crate::syntax::{self, FunctionDefinition, SyntaxError};
+use crate::special_variables::SpecialVariables;
+use crate::utils::{glob_match, io_error_message};
+use crate::variables::{split_subscript, AssignmentValue, Variable, Variables};
+
+/// Options understood by `shopt`.
+pub const SHELL_OPTIONS: [&str; 2] = ["autopushd", "lastpipe"];
+
+/// Options understood by `set -o`, with their single-letter flag.
+pub const SET_OPTIONS: [(&str, Option<char>); 2] = [("histexpand", Some('H')), ("noclobber", Some('C'))];
 
 
 pub struct Shell {
-    pub executables: Vec<Executable>,
+    /// Where commands were found on PATH; shared with tab completion.
+    pub commands: Rc<RefCell<CommandHash>>,
+    /// Aliases from `alias`; also shared with tab completion.
+    pub aliases: Rc<RefCell<Aliases>>,
     pub path: String,
     pub read_line: Editor<MyHelper, FileHistory>,
+    /// Whether commands are read from a terminal through the line editor.
+    pub interactive: bool,
+    // the start of a line whose read was interrupted by a signal
+    partial_input: Vec<u8>,
     pub current_dir: PathBuf,
+    /// The directory stack below the current directory, most recently pushed first.
+    pub dir_stack: Vec<PathBuf>,
+    /// Visited directories, for `z`.
+    pub frecency: Frecency,
     pub prompt: String,
     pub builtins: Vec<String>,
     pub last_written_index: usize,
-    pub history_file: PathBuf
+    /// How many lines of the history file have been read or written, for `history -n`.
+    pub history_file_lines: usize,
+    /// Whether the line being run was added to the history, for `history -s` and `-p`.
+    pub history_line_added: bool,
+    pub history_file: PathBuf,
+    pub last_status: i32,
+    pub traps: HashMap<String, String>,
+    pub in_trap: bool,
+    pub variables: Variables,
+    pub options: HashSet<String>,
+    /// Descriptors opened with `exec`, inherited by every command.
+    pub fds: FdTable,
+    /// `$0` followed by the positional parameters `$1`, `$2`, ...
+    pub positional: Vec<String>,
+    pub special_variables: SpecialVariables,
+    /// `$$`: the shell's pid, which subshells keep.
+    pub pid: u32,
+    pub parent_pid: i32,
+    /// `$LINENO`: the line being run in a script, or the count of lines read interactively.
+    pub line_number: usize,
+    /// A `break` or `continue` on its way out to the loop it applies to.
+    pub jump: Option<Jump>,
+    /// How many loops the command being run is nested in.
+    pub loop_depth: usize,
+    /// Shell functions by name.
+    pub functions: HashMap<String, Rc<FunctionDefinition>>,
+    /// The functions being run, innermost last.
+    pub call_stack: Vec<Frame>,
+    /// How many `if`/`while` conditions or `&&`/`||` operands the command being run is part
+    /// of; their failures don't trigger the ERR trap.
+    pub condition_depth: usize,
+}
 
+/// How `break n`, `continue n` or `return` leaves the loops or function it is in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jump {
+    Break(usize),
+    Continue(usize),
+    Return,
 }
-pub struct Executable {
+
+/// A running shell function.
+pub struct Frame {
     pub name: String,
-    pub path: PathBuf,
+    /// The variables `local` shadowed, to be restored when the function returns.
+    pub locals: Vec<(String, Option<Variable>)>,
 }
 pub enum ShellAction {
     Continue,
-    Error(String),
+    Status(i32),
     Exit,
 }
+impl ShellAction {
+    pub fn from_status(status: i32) -> Self {
+        match status {
+            0 => ShellAction::Continue,
+            status => ShellAction::Status(status),
+        }
+    }
+
+    /// The exit status of a finished command, or `None` when the shell is exiting.
+    pub fn status(&self) -> Option<i32> {
+        match self {
+            ShellAction::Continue => Some(0),
+            ShellAction::Status(status) => Some(*status),
+            ShellAction::Exit => None,
+        }
+    }
+}
 impl Shell {
     pub fn new(path: &str, prompt: &str, builtins: Vec<String>, history_env_key: &str) -> Self {
         let history_file = PathBuf::from(env::var(history_env_key).unwrap_or_default());
+        let mut shell = Shell::with_history_file(path, prompt, builtins, history_file);
+        if shell.history_file.exists() {
+            let _ = shell.read_line.load_history(shell.history_file.as_path());
+            shell.last_written_index = shell.read_line.history().len();
+            shell.history_file_lines = shell.last_written_index;
+        }
+        signals::init();
+        shell
+    }
+
+    /// A shell for unit tests, which leaves the history file and signal handlers alone.
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        Shell::with_history_file("PATH", "$ ", Vec::new(), PathBuf::new())
+    }
+
+    // the shell's state, without loading the history or installing signal handlers; an empty
+    // `history_file` keeps history and visited directories for this session only
+    fn with_history_file(
+        path: &str,
+        prompt: &str,
+        builtins: Vec<String>,
+        history_file: PathBuf,
+    ) -> Self {
+        let commands = Rc::new(RefCell::new(CommandHash::new()));
+        let aliases = Rc::new(RefCell::new(Aliases::new()));
         let mut shell = Shell {
-            executables: Vec::new(),
+            commands: Rc::clone(&commands),
+            aliases: Rc::clone(&aliases),
             path: path.to_string(),
             prompt: prompt.to_string(),
             read_line: Editor::<MyHelper, FileHistory>::new().unwrap(),
-            current_dir: env::current_dir().unwrap(),
+            interactive: unsafe { libc::isatty(0) } == 1,
+            partial_input: Vec::new(),
+            current_dir: logical_current_dir(),
+            dir_stack: Vec::new(),
+            frecency: Frecency::load(&history_file),
             builtins,
             last_written_index: 0,
-            history_file
-
+            history_file_lines: 0,
+            history_line_added: false,
+            history_file,
+            last_status: 0,
+            traps: HashMap::new(),
+            in_trap: false,
+            variables: Variables::from_env(),
+            options: HashSet::new(),
+            fds: FdTable::new(),
+            positional: vec![env::args().next().unwrap_or_else(|| "bash".to_string())],
+            special_variables: SpecialVariables::new(),
+            pid: std::process::id(),
+            parent_pid: unsafe { libc::getppid() },
+            line_number: 0,
+            jump: None,
+            loop_depth: 0,
+            functions: HashMap::new(),
+            call_stack: Vec::new(),
+            condition_depth: 0,
         };
-        if shell.history_file.exists() {
-            let _ = shell.read_line.load_history(shell.history_file.as_path());
-            shell.last_written_index = shell.read_line.history().len();
+        shell.init_variables();
+        // like bash, history expansion is only on by default when commands are typed
+        if shell.interactive {
+            shell.options.insert("histexpand".to_string());
         }
-
+        // `$HISTCONTROL` decides which duplicates are left out
+        let _ = shell.read_line.history_mut().ignore_dups(false);
+        shell.apply_history_size();
         shell.read_line.set_completion_type(CompletionType::List);
-        shell.read_line.set_helper(Some(MyHelper::new()));
-        shell.get_executables();
+        shell.read_line.set_helper(Some(MyHelper::new(commands, aliases)));
+        shell.sync_command_path();
         shell
     }
 
-    fn get_executables(&mut self) {
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.special_variables.get(name, &self.variables) {
+            return Some(value);
+        }
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "PPID" => Some(self.parent_pid.to_string()),
+            "LINENO" => Some(self.line_number.to_string()),
+            "#" => Some((self.positional.len() - 1).to_string()),
+            "@" | "*" => Some(self.positional[1..].join(" ")),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                self.positional.get(name.parse::<usize>().ok()?).cloned()
+            }
+            _ => self.variables.get(name),
+        }
+    }
 
-        if !self.executables.is_empty() {
-            return;
+    // `SHLVL` counts one more level than the shell that started us, and `PWD` starts out as
+    // the working directory
+    fn init_variables(&mut self) {
+        let level = self.variables.get("SHLVL").and_then(|l| l.trim().parse::<i64>().ok());
+        let _ = self.variables.set("SHLVL", (level.unwrap_or(0) + 1).to_string());
+        self.variables.attributes_mut("SHLVL").exported = true;
+        let _ = self.variables.set("PWD", self.current_dir.display().to_string());
+        self.variables.attributes_mut("PWD").exported = true;
+    }
+
+    /// The directory stack as `dirs` shows it: the current directory, then the saved ones.
+    pub fn directories(&self) -> Vec<PathBuf> {
+        [self.current_dir.clone()].into_iter().chain(self.dir_stack.iter().cloned()).collect()
+    }
+
+    /// Where `+N`, `-N` or plain `N` points in [`Shell::directories`]: `+N` counts from the
+    /// current directory, `-N` from the bottom of the stack.
+    pub fn dir_stack_index(&self, position: &str) -> Option<usize> {
+        let (from_bottom, digits) = match position.split_at_checked(1) {
+            Some(("-", digits)) => (true, digits),
+            Some(("+", digits)) => (false, digits),
+            _ => (false, position),
+        };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let n: usize = digits.parse().ok()?;
+        let len = self.dir_stack.len() + 1;
+        match from_bottom {
+            true => len.checked_sub(n + 1),
+            false => (n < len).then_some(n),
+        }
+    }
+
+    /// The `(key, value)` pairs of an array, or of the positional parameters for `@` and `*`.
+    pub fn elements(&self, name: &str) -> Vec<(String, String)> {
+        match name {
+            "@" | "*" => (1..)
+                .map(|i: usize| i.to_string())
+                .zip(self.positional[1..].iter().cloned())
+                .collect(),
+            _ => self.variables.elements(name),
+        }
+    }
+
+    /// Parses and runs a complete command line, or the lines of a script read so far. A
+    /// syntax error ends a script, as in bash.
+    pub fn run_command(&mut self, input: &str) -> ShellAction {
+        match syntax::parse(input) {
+            Ok(list) => self.run_list(&list),
+            Err(error) => {
+                eprintln!("bash: {}", error);
+                self.last_status = 2;
+                match self.interactive {
+                    true => ShellAction::Status(2),
+                    false => ShellAction::Exit,
+                }
+            }
         }
-        if let Ok(path_var) = env::var(&self.path) {
-            for path in env::split_paths(&path_var) {
-                if let Ok(entries) = std::fs::read_dir(path) {
-                    for entry in entries.flatten() {
-                        let filename = entry.file_name().to_string_lossy().to_string();
-
-                        if is_executable(&entry.path()) {
-                            self.executables.push(Executable {
-                                name: filename,
-                                path: entry.path(),
-                            });
-                        }
+    }
+
+    /// Runs the commands of `script` like the interactive loop would, until the end or
+    /// `exit`. Returns the status of the last command.
+    pub fn run_script(&mut self, script: &str) -> i32 {
+        let line_number = self.line_number;
+        let mut input = String::new();
+        let mut lines = script.lines().enumerate();
+        while let Some((index, line)) = lines.next() {
+            if input.is_empty() {
+                self.line_number = index + 1;
+            }
+            input.push_str(line);
+            input.push('\n');
+            for here_document in find_here_documents(line) {
+                for (_, body_line) in lines.by_ref() {
+                    input.push_str(body_line);
+                    input.push('\n');
+                    if here_document.is_delimiter(body_line) {
+                        break;
                     }
                 }
             }
+            // a compound command can go on over several lines
+            if let Err(SyntaxError::Incomplete) = syntax::parse(&input) {
+                continue;
+            }
+            if let ShellAction::Exit = self.run_command(&mem::take(&mut input)) {
+                break;
+            }
+        }
+        // reports the command left unfinished at the end of the script
+        if !input.is_empty() {
+            self.run_command(&input);
+        }
+        self.line_number = line_number;
+        self.last_status
+    }
+
+    /// Runs the script at `path` with `args` as its positional parameters, as for a file that
+    /// is executable but has no `#!` line.
+    pub fn run_script_file(&mut self, path: &Path, name: &str, args: &[String]) -> i32 {
+        let script = match fs::read(path) {
+            Ok(script) => String::from_utf8_lossy(&script).into_owned(),
+            Err(e) => {
+                eprintln!("bash: {}: {}", name, io_error_message(&e));
+                return 126;
+            }
+        };
+        self.reset_traps();
+        self.positional = [name.to_string()].into_iter().chain(args.iter().cloned()).collect();
+        self.last_status = 0;
+        let status = self.run_script(&script);
+        self.run_trap("EXIT");
+        status
+    }
+
+    /// Sets `FOO=1`-style prefixes as exported variables for one command. Returns the previous
+    /// values for [`Shell::restore_variables`].
+    pub fn assign_temporarily(
+        &mut self,
+        assignments: &[(String, AssignmentValue)],
+    ) -> Result<Vec<(String, Option<Variable>)>, String> {
+        let mut saved = Vec::new();
+        for (target, value) in assignments {
+            let (name, subscript) = split_subscript(target.strip_suffix('+').unwrap_or(target));
+            saved.push((name.to_string(), self.variables.get_variable(name).cloned()));
+            if let Err(e) = self.variables.assign(target, value) {
+                self.restore_variables(saved);
+                return Err(e);
+            }
+            if subscript.is_none() {
+                self.variables.attributes_mut(name).exported = true;
+            }
+        }
+        Ok(saved)
+    }
+
+    pub fn restore_variables(&mut self, saved: Vec<(String, Option<Variable>)>) {
+        for (name, variable) in saved.into_iter().rev() {
+            self.variables.restore(&name, variable);
+        }
+    }
+
+    /// Reads the next line of commands. A terminal goes through the line editor; other
+    /// input is read a byte at a time straight from fd 0, so nothing is buffered ahead of
+    /// the commands that read the rest of it, and a signal interrupts the wait.
+    pub fn read_input_line(&mut self, prompt: &str) -> rustyline::Result<String> {
+        if self.interactive {
+            return self.read_line.readline(prompt);
+        }
+        let mut line = mem::take(&mut self.partial_input);
+        loop {
+            if signals::has_pending() {
+                self.partial_input = line;
+                return Err(ReadlineError::Interrupted);
+            }
+            let mut byte = [0u8];
+            match unsafe { libc::read(0, byte.as_mut_ptr().cast(), 1) } {
+                0 if line.is_empty() => return Err(ReadlineError::Eof),
+                0 => break,
+                1 if byte[0] == b'\n' => break,
+                1 => line.push(byte[0]),
+                _ => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(ReadlineError::Io(e));
+                    }
+                }
+            }
+        }
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// The history list, oldest entry first.
+    pub fn history_entries(&self) -> Vec<String> {
+        self.read_line.history().iter().cloned().collect()
+    }
+
+    /// Replaces the history list with `entries`, as after deleting some of them.
+    pub fn replace_history(&mut self, entries: Vec<String>) {
+        let history = self.read_line.history_mut();
+        let _ = history.clear();
+        for entry in entries {
+            let _ = history.add_owned(entry);
+        }
+    }
+
+    /// Adds a line read at the prompt to the history, unless `$HISTCONTROL` or
+    /// `$HISTIGNORE` leave it out. `leading_blank` is whether the line as typed started
+    /// with a blank, for `ignorespace`.
+    pub fn add_history(&mut self, line: &str, leading_blank: bool) {
+        self.history_line_added = false;
+        self.apply_history_size();
+        let control = self.get_var("HISTCONTROL").unwrap_or_default();
+        let control: Vec<&str> = control.split(':').collect();
+        let ignore_space = control.contains(&"ignorespace") || control.contains(&"ignoreboth");
+        let ignore_dups = control.contains(&"ignoredups") || control.contains(&"ignoreboth");
+        let mut entries = self.history_entries();
+        let previous = entries.last().map(String::as_str);
+        if leading_blank && ignore_space || ignore_dups && previous == Some(line) {
+            return;
+        }
+        // `&` stands for the previous line
+        let ignore = self.get_var("HISTIGNORE").unwrap_or_default();
+        let ignored = ignore.split(':').filter(|pattern| !pattern.is_empty()).any(|pattern| match pattern {
+            "&" => previous == Some(line),
+            pattern => glob_match(pattern, line),
+        });
+        if ignored {
+            return;
+        }
+
+        if control.contains(&"erasedups") && entries.iter().any(|entry| entry == line) {
+            let written_erased = entries[..self.last_written_index.min(entries.len())]
+                .iter()
+                .filter(|entry| *entry == line)
+                .count();
+            entries.retain(|entry| entry != line);
+            self.replace_history(entries);
+            self.last_written_index -= written_erased;
+        }
+        let history = self.read_line.history_mut();
+        let len = history.len();
+        // at `$HISTSIZE` the oldest entry makes room, so the unwritten ones move down
+        self.history_line_added = history.add(line).unwrap_or(false);
+        if self.history_line_added && history.len() == len {
+            self.last_written_index = self.last_written_index.saturating_sub(1);
+        }
+    }
+
+    /// Limits the history list to `$HISTSIZE` entries, without a limit when it is
+    /// negative, or to the editor's default when it is unset or not a number.
+    fn apply_history_size(&mut self) {
+        let size = match self.get_var("HISTSIZE").and_then(|size| size.trim().parse::<i64>().ok()) {
+            Some(size) => usize::try_from(size).unwrap_or(usize::MAX),
+            None => Config::default().max_history_size(),
+        };
+        let history = self.read_line.history_mut();
+        let dropped = history.len().saturating_sub(size);
+        let _ = history.set_max_len(size);
+        self.last_written_index = self.last_written_index.saturating_sub(dropped);
+    }
+
+    /// Resolves a command name on the current PATH through the hash table.
+    pub fn find_command(&self, name: &str) -> Option<PathBuf> {
+        self.sync_command_path();
+        self.commands.borrow_mut().find(name)
+    }
+
+    /// Where a command name would be run from, for queries such as `type` that mustn't
+    /// change the hash table.
+    pub fn lookup_command(&self, name: &str) -> Option<PathBuf> {
+        self.sync_command_path();
+        self.commands.borrow().lookup(name)
+    }
+
+    /// Forgets hashed locations if PATH changed since the last lookup.
+    pub fn sync_command_path(&self) {
+        self.commands.borrow_mut().set_path(self.get_var(&self.path));
+    }
+}
+
+// `$PWD` from the environment when it names the working directory, which keeps the path
+// the parent reached it by, symlinks and all; otherwise the physical path
+fn logical_current_dir() -> PathBuf {
+    let physical = env::current_dir().unwrap();
+    match env::var_os("PWD").map(PathBuf::from) {
+        Some(pwd) if pwd.is_absolute() && pwd.canonicalize().ok().as_ref() == Some(&physical) => {
+            normalize_path(&pwd)
         }
+        _ => physical,
     }
 }
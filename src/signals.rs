@@ -0,0 +1,239 @@
+use std::fs;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use crate::builtin::history;
+use crate::shell::{Shell, ShellAction};
+
+/// Trap conditions that are not real signals but are raised by the shell itself.
+pub const PSEUDO_SIGNALS: [&str; 4] = ["EXIT", "DEBUG", "ERR", "RETURN"];
+
+const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+pub enum Disposition {
+    Default,
+    Ignore,
+    Catch,
+}
+
+static PENDING: AtomicU64 = AtomicU64::new(0);
+static AT_PROMPT: AtomicBool = AtomicBool::new(false);
+// rustyline's own SIGINT handler, used to break out of a blocking readline
+static PROMPT_WAKER: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_ACTIONS: Mutex<Vec<(libc::c_int, libc::sigaction)>> = Mutex::new(Vec::new());
+
+extern "C" fn record_signal(sig: libc::c_int) {
+    PENDING.fetch_or(1 << sig, Ordering::SeqCst);
+    if !AT_PROMPT.load(Ordering::SeqCst) {
+        return;
+    }
+    // The line editor retries interrupted reads, so its own handler has to break out of
+    // them. Without the editor the read at the prompt fails with EINTR by itself, as the
+    // handlers are installed without SA_RESTART, and the main loop takes over.
+    let waker = PROMPT_WAKER.load(Ordering::SeqCst);
+    if waker != 0 {
+        let waker: extern "C" fn(libc::c_int) = unsafe { mem::transmute(waker) };
+        waker(libc::SIGINT);
+    }
+}
+
+/// Must be called after the line editor is created so its SIGINT handler can be captured.
+pub fn init() {
+    unsafe {
+        let mut current: libc::sigaction = mem::zeroed();
+        if libc::sigaction(libc::SIGINT, std::ptr::null(), &mut current) == 0
+            && current.sa_sigaction != libc::SIG_DFL
+            && current.sa_sigaction != libc::SIG_IGN
+        {
+            PROMPT_WAKER.store(current.sa_sigaction, Ordering::SeqCst);
+        }
+    }
+    set_disposition(libc::SIGHUP, Disposition::Catch);
+    set_disposition(libc::SIGTERM, Disposition::Catch);
+}
+
+pub fn set_disposition(sig: libc::c_int, disposition: Disposition) {
+    let mut originals = ORIGINAL_ACTIONS.lock().unwrap();
+    let original = originals.iter().find(|(s, _)| *s == sig).map(|(_, a)| *a);
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        match disposition {
+            Disposition::Default => match original {
+                Some(original) => action = original,
+                None => return,
+            },
+            Disposition::Ignore => action.sa_sigaction = libc::SIG_IGN,
+            Disposition::Catch => action.sa_sigaction = record_signal as *const () as usize,
+        }
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut previous: libc::sigaction = mem::zeroed();
+        if libc::sigaction(sig, &action, &mut previous) == 0 && original.is_none() {
+            originals.push((sig, previous));
+        }
+    }
+}
+
+pub fn set_at_prompt(at_prompt: bool) {
+    AT_PROMPT.store(at_prompt, Ordering::SeqCst);
+}
+
+pub fn has_pending() -> bool {
+    PENDING.load(Ordering::SeqCst) != 0
+}
+
+fn take_pending() -> Vec<libc::c_int> {
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    (1..64).filter(|sig| pending & (1 << sig) != 0).collect()
+}
+
+/// Resolves `INT`, `SIGINT`, `int` or `2` to the canonical condition name.
+pub fn condition_name(spec: &str) -> Option<&'static str> {
+    if let Ok(number) = spec.parse::<libc::c_int>() {
+        return match number {
+            0 => Some("EXIT"),
+            n => signal_name(n),
+        };
+    }
+    let upper = spec.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    PSEUDO_SIGNALS
+        .iter()
+        .copied()
+        .chain(SIGNALS.iter().map(|(n, _)| *n))
+        .find(|n| *n == name)
+}
+
+pub fn signal_number(name: &str) -> Option<libc::c_int> {
+    SIGNALS.iter().find(|(n, _)| *n == name).map(|(_, num)| *num)
+}
+
+pub fn signal_name(number: libc::c_int) -> Option<&'static str> {
+    SIGNALS.iter().find(|(_, n)| *n == number).map(|(name, _)| *name)
+}
+
+pub fn signals() -> &'static [(&'static str, libc::c_int)] {
+    SIGNALS
+}
+
+/// All trap conditions in the order `trap -p` prints them.
+pub fn conditions() -> impl Iterator<Item = &'static str> {
+    std::iter::once("EXIT")
+        .chain(SIGNALS.iter().map(|(name, _)| *name))
+        .chain(PSEUDO_SIGNALS.iter().copied().skip(1))
+}
+
+impl Shell {
+    pub fn run_trap(&mut self, condition: &str) -> ShellAction {
+        if self.in_trap {
+            return ShellAction::Continue;
+        }
+        let action = match self.traps.get(condition) {
+            Some(action) if !action.is_empty() => action.clone(),
+            _ => return ShellAction::Continue,
+        };
+        let status = self.last_status;
+        self.in_trap = true;
+        let result = self.run_command(&action);
+        self.in_trap = false;
+        // `exit` in the handler sets the status the shell leaves with
+        if !matches!(result, ShellAction::Exit) {
+            self.last_status = status;
+        }
+        result
+    }
+
+    /// Runs the handlers of signals received since the last call. HUP and TERM terminate the
+    /// shell after saving history unless they are trapped.
+    pub fn handle_pending_signals(&mut self) -> ShellAction {
+        for sig in take_pending() {
+            let Some(name) = signal_name(sig) else {
+                continue;
+            };
+            if self.traps.contains_key(name) {
+                if let ShellAction::Exit = self.run_trap(name) {
+                    return ShellAction::Exit;
+                }
+            } else if sig == libc::SIGHUP || sig == libc::SIGTERM {
+                self.run_trap("EXIT");
+                self.save_history();
+                unsafe {
+                    libc::signal(sig, libc::SIG_DFL);
+                    libc::raise(sig);
+                }
+            }
+        }
+        ShellAction::Continue
+    }
+
+    /// Drops the traps for a script subshell. Ignored signals stay ignored, as POSIX requires.
+    pub fn reset_traps(&mut self) {
+        self.traps.retain(|_, action| action.is_empty());
+        for (name, sig) in signals() {
+            if !self.traps.contains_key(*name) && *sig != libc::SIGHUP && *sig != libc::SIGTERM {
+                set_disposition(*sig, Disposition::Default);
+            }
+        }
+    }
+
+    pub fn save_history(&mut self) {
+        let file = self.history_file.clone();
+        history(
+            self,
+            &["-a".to_string(), file.to_str().unwrap_or_default().to_string()],
+            &mut std::io::stdout(),
+            &mut std::io::stderr(),
+        );
+        self.truncate_history_file();
+    }
+
+    // keeps only the last `$HISTFILESIZE` lines of the history file; unset, negative or
+    // not a number leaves it alone
+    fn truncate_history_file(&mut self) {
+        let size = self.get_var("HISTFILESIZE").and_then(|size| size.trim().parse::<i64>().ok());
+        let Some(size) = size.and_then(|size| usize::try_from(size).ok()) else {
+            return;
+        };
+        let Ok(contents) = fs::read_to_string(&self.history_file) else {
+            return;
+        };
+        let lines: Vec<&str> = contents.lines().collect();
+        if lines.len() <= size {
+            return;
+        }
+        let kept: String = lines[lines.len() - size..].iter().map(|line| format!("{}\n", line)).collect();
+        if fs::write(&self.history_file, kept).is_ok() {
+            self.history_file_lines = size;
+        }
+    }
+}
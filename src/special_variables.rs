@@ -0,0 +1,72 @@
+use std::cell::Cell;
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::variables::Variables;
+
+/// The variables bash computes on every read: `RANDOM`, `SECONDS`, `BASHPID`, `EPOCHSECONDS`
+/// and `EPOCHREALTIME`. Assigning `RANDOM` reseeds the generator and assigning `SECONDS`
+/// restarts the count from the assigned value; the assignment itself lands in [`Variables`]
+/// like any other, and is noticed here the next time the variable is read.
+pub struct SpecialVariables {
+    random: Cell<u32>,
+    // the write count of `RANDOM` when it was last used as a seed
+    random_seeded: Cell<u64>,
+    // the write count of `SECONDS` when the count last restarted, and when that was
+    seconds_started: Cell<(u64, Instant)>,
+}
+
+impl SpecialVariables {
+    pub fn new() -> Self {
+        SpecialVariables {
+            random: Cell::new(since_epoch().subsec_nanos() ^ process::id()),
+            random_seeded: Cell::new(0),
+            seconds_started: Cell::new((0, Instant::now())),
+        }
+    }
+
+    /// The value of `name` if it is one of the computed variables.
+    pub fn get(&self, name: &str, variables: &Variables) -> Option<String> {
+        match name {
+            "RANDOM" => Some(self.next_random(variables).to_string()),
+            "SECONDS" => Some(self.seconds(variables).to_string()),
+            "BASHPID" => Some(process::id().to_string()),
+            "EPOCHSECONDS" => Some(since_epoch().as_secs().to_string()),
+            "EPOCHREALTIME" => {
+                let now = since_epoch();
+                Some(format!("{}.{:06}", now.as_secs(), now.subsec_micros()))
+            }
+            _ => None,
+        }
+    }
+
+    // a 15-bit value, 0 to 32767, from a linear congruential generator
+    fn next_random(&self, variables: &Variables) -> u32 {
+        let writes = variables.writes("RANDOM");
+        if writes != self.random_seeded.get() {
+            self.random.set(integer(variables.get("RANDOM")) as u32);
+            self.random_seeded.set(writes);
+        }
+        let state = self.random.get().wrapping_mul(1103515245).wrapping_add(12345);
+        self.random.set(state);
+        (state >> 16) & 0x7fff
+    }
+
+    fn seconds(&self, variables: &Variables) -> i64 {
+        let writes = variables.writes("SECONDS");
+        let (mut started_at, mut started) = self.seconds_started.get();
+        if writes != started_at {
+            (started_at, started) = (writes, Instant::now());
+            self.seconds_started.set((started_at, started));
+        }
+        integer(variables.get("SECONDS")) + started.elapsed().as_secs() as i64
+    }
+}
+
+fn integer(value: Option<String>) -> i64 {
+    value.and_then(|value| value.trim().parse().ok()).unwrap_or(0)
+}
+
+fn since_epoch() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
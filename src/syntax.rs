@@ -0,0 +1,628 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::redirection::find_here_documents;
+use crate::variables::is_valid_name;
+
+/// Words that start or end a compound command where a command name could go.
+pub const RESERVED_WORDS: [&str; 15] = [
+    "!", "{", "}", "if", "then", "elif", "else", "fi", "while", "until", "for", "in", "do", "done",
+    "function",
+];
+
+/// Commands run one after another, as separated by `;` or newlines.
+pub type List = Vec<AndOr>;
+
+/// Pipelines joined by `&&` and `||`.
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    /// `! pipeline` inverts the status.
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// A simple command as written. It is only expanded when it runs, so a loop sees the
+    /// variables of the current iteration.
+    Simple(CommandText),
+    /// A compound command and the redirections written after it.
+    Compound(CompoundCommand, CommandText),
+    /// `name () compound-command` or `function name compound-command`.
+    Function(Rc<FunctionDefinition>),
+}
+
+#[derive(Debug)]
+pub struct FunctionDefinition {
+    pub name: String,
+    /// A compound command, with its redirections.
+    pub body: Command,
+    /// The definition as written, for `type`.
+    pub text: String,
+}
+
+/// Words as written, and the lines holding the bodies of the here-documents they open.
+#[derive(Debug, Clone, Default)]
+pub struct CommandText {
+    pub text: String,
+    pub here_documents: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum CompoundCommand {
+    /// `{ list; }`
+    Group(List),
+    /// `( list )`, run in a subshell.
+    Subshell(List),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while list; do list; done`, or `until` when `until` is set.
+    While {
+        until: bool,
+        condition: List,
+        body: List,
+    },
+    /// `for name [in word...]; do list; done`. Without `in`, the loop runs over `"$@"`.
+    For {
+        name: String,
+        words: Option<String>,
+        body: List,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxError {
+    /// The input ends in the middle of a command, so more lines are needed.
+    Incomplete,
+    /// A token that can't appear where it does.
+    Unexpected(String),
+    /// A command ended with `&`. Without job control it can't be put in the background,
+    /// and running it in the foreground instead would change what the line does.
+    Background,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntaxError::Incomplete => write!(f, "syntax error: unexpected end of file"),
+            SyntaxError::Unexpected(token) => {
+                write!(f, "syntax error near unexpected token `{}'", token)
+            }
+            SyntaxError::Background => write!(f, "`&': background jobs are not supported"),
+        }
+    }
+}
+
+/// Parses a complete command line, or several lines of a script, into the list of commands
+/// to run. [`SyntaxError::Incomplete`] means the input stops inside a command, such as a
+/// `while` without its `done`, and could go on in the next line.
+pub fn parse(input: &str) -> Result<List, SyntaxError> {
+    let (text, bodies) = take_here_documents(input);
+    let mut parser = Parser {
+        lexer: Lexer {
+            chars: text.chars().collect(),
+            position: 0,
+            conditional: false,
+        },
+        peeked: None,
+        consumed: 0,
+        bodies,
+    };
+    let list = parser.list(&[])?;
+    match parser.next()? {
+        Token::Eof => Ok(list),
+        token => Err(unexpected(token)),
+    }
+}
+
+// Moves the body lines of here-documents out of the way of the parser. The bodies, with
+// their delimiter lines, come back in the order their operators appear.
+fn take_here_documents(input: &str) -> (String, VecDeque<String>) {
+    let mut text = String::new();
+    let mut bodies = VecDeque::new();
+    let mut lines = input.lines();
+    while let Some(line) = lines.next() {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(line);
+        for here_document in find_here_documents(line) {
+            let mut body = Vec::new();
+            for body_line in lines.by_ref() {
+                body.push(body_line);
+                if here_document.is_delimiter(body_line) {
+                    break;
+                }
+            }
+            bodies.push_back(body.join("\n"));
+        }
+    }
+    (text, bodies)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(WordToken),
+    Operator(&'static str),
+    Newline,
+    Eof,
+}
+
+// a word as written, quotes and all, and the characters of the input it spans
+#[derive(Debug, Clone, PartialEq)]
+struct WordToken {
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+fn unexpected(token: Token) -> SyntaxError {
+    match token {
+        Token::Word(word) => SyntaxError::Unexpected(word.text),
+        Token::Operator(operator) => SyntaxError::Unexpected(operator.to_string()),
+        Token::Newline => SyntaxError::Unexpected("newline".to_string()),
+        Token::Eof => SyntaxError::Incomplete,
+    }
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    position: usize,
+    // inside `[[ ]]`, where `&&`, `||`, `(` and `)` belong to the expression
+    conditional: bool,
+}
+
+impl Lexer {
+    fn peek_char(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn next_token(&mut self) -> Result<Token, SyntaxError> {
+        loop {
+            match (self.peek_char(0), self.peek_char(1)) {
+                (Some(' ' | '\t'), _) => self.position += 1,
+                (Some('\\'), Some('\n')) => self.position += 2,
+                (Some('#'), _) => {
+                    while self.peek_char(0).is_some_and(|c| c != '\n') {
+                        self.position += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        let operator = match (self.peek_char(0), self.peek_char(1)) {
+            (None, _) => return Ok(Token::Eof),
+            (Some('\n'), _) => {
+                self.position += 1;
+                return Ok(Token::Newline);
+            }
+            (Some('&'), Some('&')) if !self.conditional => "&&",
+            (Some('|'), Some('|')) if !self.conditional => "||",
+            (Some(';'), _) => ";",
+            (Some('|'), next) if !self.conditional || next != Some('|') => "|",
+            (Some('&'), next) if next != Some('>') && next != Some('&') => "&",
+            (Some('('), _) if !self.conditional => "(",
+            (Some(')'), _) if !self.conditional => ")",
+            _ => return self.word().map(Token::Word),
+        };
+        self.position += operator.len();
+        Ok(Token::Operator(operator))
+    }
+
+    fn word(&mut self) -> Result<WordToken, SyntaxError> {
+        let start = self.position;
+        while let Some(c) = self.peek_char(0) {
+            match c {
+                ' ' | '\t' | '\n' | ';' => break,
+                '&' if self.peek_char(1) == Some('>') => self.position += 2,
+                '&' | '|' if self.conditional && self.peek_char(1) == Some(c) => self.position += 2,
+                '&' | '|' => break,
+                '(' | ')' if self.conditional => self.position += 1,
+                // the elements of a compound assignment such as `arr=(a b)`
+                '(' if self.position > start && self.chars[self.position - 1] == '=' => {
+                    self.skip_nested('(', ')')?
+                }
+                '(' | ')' => break,
+                // `>&`, `<&` and `>|` are single operators
+                '>' | '<' => {
+                    self.position += 1;
+                    if matches!(self.peek_char(0), Some('&' | '|')) {
+                        self.position += 1;
+                    }
+                }
+                '\\' => {
+                    if self.peek_char(1).is_none() {
+                        return Err(SyntaxError::Incomplete);
+                    }
+                    self.position += 2;
+                }
+                '\'' => self.skip_single_quotes()?,
+                '"' => self.skip_double_quotes()?,
+                '`' => self.skip_backquotes()?,
+                '$' => self.skip_dollar()?,
+                _ => self.position += 1,
+            }
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        Ok(WordToken {
+            text: text.replace("\\\n", ""),
+            start,
+            end: self.position,
+        })
+    }
+
+    fn skip_single_quotes(&mut self) -> Result<(), SyntaxError> {
+        self.position += 1;
+        loop {
+            match self.peek_char(0) {
+                None => return Err(SyntaxError::Incomplete),
+                Some('\'') => break,
+                Some(_) => self.position += 1,
+            }
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn skip_double_quotes(&mut self) -> Result<(), SyntaxError> {
+        self.position += 1;
+        loop {
+            match self.peek_char(0) {
+                None => return Err(SyntaxError::Incomplete),
+                Some('"') => break,
+                Some('\\') => self.position += 2,
+                Some('`') => self.skip_backquotes()?,
+                Some('$') => self.skip_dollar()?,
+                Some(_) => self.position += 1,
+            }
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn skip_backquotes(&mut self) -> Result<(), SyntaxError> {
+        self.position += 1;
+        loop {
+            match self.peek_char(0) {
+                None => return Err(SyntaxError::Incomplete),
+                Some('`') => break,
+                Some('\\') => self.position += 2,
+                Some(_) => self.position += 1,
+            }
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    // `${...}` and `$(...)` may hold blanks, quotes and operators of their own
+    fn skip_dollar(&mut self) -> Result<(), SyntaxError> {
+        self.position += 1;
+        match self.peek_char(0) {
+            Some('{') => self.skip_nested('{', '}'),
+            Some('(') => self.skip_nested('(', ')'),
+            _ => Ok(()),
+        }
+    }
+
+    // from an opening bracket to the one that closes it, skipping quoted text
+    fn skip_nested(&mut self, open: char, close: char) -> Result<(), SyntaxError> {
+        let mut depth = 0;
+        loop {
+            match self.peek_char(0) {
+                None => return Err(SyntaxError::Incomplete),
+                Some('\\') => self.position += 2,
+                Some('\'') => self.skip_single_quotes()?,
+                Some('"') => self.skip_double_quotes()?,
+                Some(c) => {
+                    self.position += 1;
+                    if c == open {
+                        depth += 1;
+                    } else if c == close {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Parser {
+    lexer: Lexer,
+    // the next token, and where it ends
+    peeked: Option<(Token, usize)>,
+    // where the last token taken ends
+    consumed: usize,
+    // here-document bodies not yet claimed by a command
+    bodies: VecDeque<String>,
+}
+
+impl Parser {
+    fn peek(&mut self) -> Result<&Token, SyntaxError> {
+        if self.peeked.is_none() {
+            let token = self.lexer.next_token()?;
+            self.peeked = Some((token, self.lexer.position));
+        }
+        Ok(&self.peeked.as_ref().unwrap().0)
+    }
+
+    fn next(&mut self) -> Result<Token, SyntaxError> {
+        let (token, end) = match self.peeked.take() {
+            Some(peeked) => peeked,
+            None => (self.lexer.next_token()?, self.lexer.position),
+        };
+        self.consumed = end;
+        Ok(token)
+    }
+
+    // whether the next token is the reserved word `word`
+    fn at_reserved(&mut self, word: &str) -> Result<bool, SyntaxError> {
+        Ok(matches!(self.peek()?, Token::Word(token) if token.text == word))
+    }
+
+    fn eat_reserved(&mut self, word: &str) -> Result<bool, SyntaxError> {
+        let found = self.at_reserved(word)?;
+        if found {
+            self.next()?;
+        }
+        Ok(found)
+    }
+
+    fn expect_reserved(&mut self, word: &str) -> Result<(), SyntaxError> {
+        match self.next()? {
+            Token::Word(token) if token.text == word => Ok(()),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn expect_operator(&mut self, operator: &str) -> Result<(), SyntaxError> {
+        match self.next()? {
+            Token::Operator(found) if found == operator => Ok(()),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), SyntaxError> {
+        while let Token::Newline = self.peek()? {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    // Commands up to one of the reserved words in `terminators`, a `)` or the end of input.
+    fn list(&mut self, terminators: &[&str]) -> Result<List, SyntaxError> {
+        let mut list = Vec::new();
+        loop {
+            self.skip_newlines()?;
+            let end = match self.peek()? {
+                Token::Eof | Token::Operator(")") => true,
+                Token::Word(token) => terminators.contains(&token.text.as_str()),
+                _ => false,
+            };
+            if end {
+                break;
+            }
+            list.push(self.and_or()?);
+            match self.peek()? {
+                Token::Operator(";") | Token::Newline => {
+                    self.next()?;
+                }
+                Token::Operator("&") => return Err(SyntaxError::Background),
+                _ => break,
+            }
+        }
+        Ok(list)
+    }
+
+    // a list that has to hold at least one command, as in the body of a loop
+    fn compound_list(&mut self, terminators: &[&str]) -> Result<List, SyntaxError> {
+        let list = self.list(terminators)?;
+        if list.is_empty() {
+            let token = self.next()?;
+            return Err(unexpected(token));
+        }
+        Ok(list)
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, SyntaxError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek()? {
+                Token::Operator("&&") => Connector::And,
+                Token::Operator("||") => Connector::Or,
+                _ => break,
+            };
+            self.next()?;
+            self.skip_newlines()?;
+            rest.push((connector, self.pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, SyntaxError> {
+        let negated = self.eat_reserved("!")?;
+        let mut commands = vec![self.command()?];
+        while let Token::Operator("|") = self.peek()? {
+            self.next()?;
+            self.skip_newlines()?;
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn command(&mut self) -> Result<Command, SyntaxError> {
+        let word = match self.next()? {
+            Token::Word(word) => word,
+            Token::Operator("(") => {
+                let list = self.compound_list(&[])?;
+                self.expect_operator(")")?;
+                return self.redirected(CompoundCommand::Subshell(list));
+            }
+            token => return Err(unexpected(token)),
+        };
+        let compound = match word.text.as_str() {
+            "{" => {
+                let list = self.compound_list(&["}"])?;
+                self.expect_reserved("}")?;
+                CompoundCommand::Group(list)
+            }
+            "if" => self.if_clause()?,
+            "while" | "until" => {
+                let condition = self.compound_list(&["do"])?;
+                self.expect_reserved("do")?;
+                let body = self.compound_list(&["done"])?;
+                self.expect_reserved("done")?;
+                CompoundCommand::While {
+                    until: word.text == "until",
+                    condition,
+                    body,
+                }
+            }
+            "for" => self.for_clause()?,
+            "function" => {
+                let name = match self.next()? {
+                    Token::Word(name) => name.text,
+                    token => return Err(unexpected(token)),
+                };
+                if let Token::Operator("(") = self.peek()? {
+                    self.next()?;
+                    self.expect_operator(")")?;
+                }
+                return self.function_definition(word.start, name);
+            }
+            reserved if RESERVED_WORDS.contains(&reserved) => {
+                return Err(SyntaxError::Unexpected(word.text));
+            }
+            // `name ()`; inside `[[ ]]` a parenthesis belongs to the expression
+            _ if word.text != "[[" && matches!(self.peek()?, Token::Operator("(")) => {
+                self.next()?;
+                self.expect_operator(")")?;
+                return self.function_definition(word.start, word.text);
+            }
+            _ => return self.simple_command(word),
+        };
+        self.redirected(compound)
+    }
+
+    // The body of a function named `name`, whose definition starts at `start`. Like bash, the
+    // body has to be a compound command.
+    fn function_definition(&mut self, start: usize, name: String) -> Result<Command, SyntaxError> {
+        self.skip_newlines()?;
+        let body = self.command()?;
+        if let Command::Simple(command) = &body {
+            let word = command.text.split_whitespace().next().unwrap_or_default();
+            return Err(SyntaxError::Unexpected(word.to_string()));
+        }
+        let text = self.lexer.chars[start..self.consumed].iter().collect();
+        Ok(Command::Function(Rc::new(FunctionDefinition { name, body, text })))
+    }
+
+    // the words after a compound command are its redirections
+    fn redirected(&mut self, compound: CompoundCommand) -> Result<Command, SyntaxError> {
+        let mut span = None;
+        while let Token::Word(word) = self.peek()? {
+            let start = span.map_or(word.start, |(start, _)| start);
+            span = Some((start, word.end));
+            self.next()?;
+        }
+        let redirections = match span {
+            Some((start, end)) => self.command_text(start, end),
+            None => CommandText::default(),
+        };
+        Ok(Command::Compound(compound, redirections))
+    }
+
+    fn simple_command(&mut self, first: WordToken) -> Result<Command, SyntaxError> {
+        self.lexer.conditional = first.text == "[[";
+        let mut end = first.end;
+        while let Token::Word(word) = self.peek()? {
+            end = word.end;
+            if word.text == "]]" {
+                self.lexer.conditional = false;
+            }
+            self.next()?;
+        }
+        self.lexer.conditional = false;
+        Ok(Command::Simple(self.command_text(first.start, end)))
+    }
+
+    // the input from `start` to `end`, with the bodies of the here-documents it opens
+    fn command_text(&mut self, start: usize, end: usize) -> CommandText {
+        let text: String = self.lexer.chars[start..end].iter().collect();
+        let text = text.replace("\\\n", "");
+        let count = find_here_documents(&text).len();
+        let bodies: Vec<String> = self.bodies.drain(..count.min(self.bodies.len())).collect();
+        CommandText {
+            text,
+            here_documents: bodies.join("\n"),
+        }
+    }
+
+    fn if_clause(&mut self) -> Result<CompoundCommand, SyntaxError> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let condition = self.compound_list(&["then"])?;
+            self.expect_reserved("then")?;
+            let body = self.compound_list(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+            if self.eat_reserved("elif")? {
+                continue;
+            }
+            if self.eat_reserved("else")? {
+                otherwise = Some(self.compound_list(&["fi"])?);
+            }
+            self.expect_reserved("fi")?;
+            return Ok(CompoundCommand::If { branches, otherwise });
+        }
+    }
+
+    fn for_clause(&mut self) -> Result<CompoundCommand, SyntaxError> {
+        let name = match self.next()? {
+            Token::Word(word) if is_valid_name(&word.text) => word.text,
+            token => return Err(unexpected(token)),
+        };
+        self.skip_newlines()?;
+        let mut words = None;
+        if self.eat_reserved("in")? {
+            let mut span = None;
+            while let Token::Word(word) = self.peek()? {
+                let start = span.map_or(word.start, |(start, _)| start);
+                span = Some((start, word.end));
+                self.next()?;
+            }
+            match self.next()? {
+                Token::Operator(";") | Token::Newline => (),
+                token => return Err(unexpected(token)),
+            }
+            let text = span.map(|(start, end)| self.lexer.chars[start..end].iter().collect());
+            words = Some(text.unwrap_or_default());
+        } else if let Token::Operator(";") = self.peek()? {
+            self.next()?;
+        }
+        self.skip_newlines()?;
+        self.expect_reserved("do")?;
+        let body = self.compound_list(&["done"])?;
+        self.expect_reserved("done")?;
+        Ok(CompoundCommand::For { name, words, body })
+    }
+}
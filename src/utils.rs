@@ -1,4 +1,7 @@
-use std::io::Write;
+use std::ffi::CString;
+use std::io::{self, Write};
+
+pub type Flags = Vec<(char, Option<String>)>;
 
 pub fn write_to_dest(dest: &mut dyn Write, content: &str) {
     if let Err(e) = writeln!(dest, "{}", content) {
@@ -28,3 +31,57 @@ pub fn split_by_delimiter<T: PartialEq + Clone>(vector: Vec<T>, delimiter: T) ->
 
     result
 }
+
+/// Splits leading options off `args` using a getopts-style `spec`, where a letter followed by
+/// `:` takes a value (`-p prompt` or `-pprompt`). Parsing stops at `--` or the first operand.
+pub fn parse_flags<'a>(args: &'a [String], spec: &str) -> Result<(Flags, &'a [String]), String> {
+    let mut flags = Vec::new();
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        if arg == "--" {
+            i += 1;
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() == 1 {
+            break;
+        }
+        i += 1;
+        for (pos, c) in arg.char_indices().skip(1) {
+            let Some(spec_pos) = spec.find(c).filter(|_| c != ':') else {
+                return Err(format!("-{}: invalid option", c));
+            };
+            if spec[spec_pos + 1..].starts_with(':') {
+                let rest = &arg[pos + c.len_utf8()..];
+                let value = if !rest.is_empty() {
+                    rest.to_string()
+                } else if let Some(value) = args.get(i) {
+                    i += 1;
+                    value.clone()
+                } else {
+                    return Err(format!("-{}: option requires an argument", c));
+                };
+                flags.push((c, Some(value)));
+                break;
+            }
+            flags.push((c, None));
+        }
+    }
+    Ok((flags, &args[i..]))
+}
+
+/// The OS description of an error without Rust's ` (os error N)` suffix.
+pub fn io_error_message(error: &io::Error) -> String {
+    let message = error.to_string();
+    match message.find(" (os error") {
+        Some(index) => message[..index].to_string(),
+        None => message,
+    }
+}
+
+/// Whether `text` matches the shell pattern `pattern` (`*`, `?`, `[...]`).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (Ok(pattern), Ok(text)) = (CString::new(pattern), CString::new(text)) else {
+        return false;
+    };
+    unsafe { libc::fnmatch(pattern.as_ptr(), text.as_ptr(), 0) == 0 }
+}
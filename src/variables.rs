@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+
+use crate::arithmetic;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Scalar(String),
+    Indexed(BTreeMap<usize, String>),
+    Associative(BTreeMap<String, String>),
+}
+
+/// The value of an assignment word: `name=value`, or the elements of `name=(...)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssignmentValue {
+    Scalar(String),
+    Compound(Vec<String>),
+}
+
+/// The attributes set by `declare`, `export` and `readonly`.
+#[derive(Debug, Clone, Default)]
+pub struct Attributes {
+    pub exported: bool,
+    pub readonly: bool,
+    pub integer: bool,
+    pub lowercase: bool,
+    pub uppercase: bool,
+}
+
+/// A shell variable. `value` is `None` for names that only carry attributes so far, such as
+/// after `export NAME` or `declare -i NAME`.
+#[derive(Debug, Clone, Default)]
+pub struct Variable {
+    pub value: Option<Value>,
+    pub attributes: Attributes,
+}
+
+/// Shell variables. The environment is imported at startup as exported variables; from then
+/// on children get only what is exported here.
+#[derive(Debug, Default)]
+pub struct Variables {
+    values: HashMap<String, Variable>,
+    // how many times each name has been written, so computed variables notice assignments
+    writes: HashMap<String, u64>,
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        Variables::default()
+    }
+
+    pub fn from_env() -> Self {
+        let mut variables = Variables::new();
+        for (name, value) in env::vars() {
+            let variable = Variable {
+                value: Some(Value::Scalar(value)),
+                attributes: Attributes {
+                    exported: true,
+                    ..Attributes::default()
+                },
+            };
+            variables.values.insert(name, variable);
+        }
+        variables
+    }
+
+    /// The scalar value of `name`, or element 0 for arrays.
+    pub fn get(&self, name: &str) -> Option<String> {
+        match self.values.get(name)?.value.as_ref()? {
+            Value::Scalar(value) => Some(value.clone()),
+            Value::Indexed(values) => values.get(&0).cloned(),
+            Value::Associative(values) => values.get("0").cloned(),
+        }
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<&Variable> {
+        self.values.get(name)
+    }
+
+    /// `${name[subscript]}`. Indexed arrays evaluate the subscript arithmetically, counting
+    /// negative indices from the end; associative arrays use it as the key.
+    pub fn get_element(&self, name: &str, subscript: &str) -> Result<Option<String>, String> {
+        match self.values.get(name).and_then(|v| v.value.as_ref()) {
+            None => Ok(None),
+            Some(Value::Associative(values)) => Ok(values.get(subscript).cloned()),
+            Some(Value::Indexed(values)) => match self.resolve_index(name, values, subscript)? {
+                Some(index) => Ok(values.get(&index).cloned()),
+                None => Ok(None),
+            },
+            Some(Value::Scalar(value)) => match self.resolve_index(name, &BTreeMap::new(), subscript)? {
+                Some(0) => Ok(Some(value.clone())),
+                _ => Ok(None),
+            },
+        }
+    }
+
+    /// The `(key, value)` pairs of `name` in order; a scalar is a one-element array.
+    pub fn elements(&self, name: &str) -> Vec<(String, String)> {
+        match self.values.get(name).and_then(|v| v.value.as_ref()) {
+            None => Vec::new(),
+            Some(Value::Scalar(value)) => vec![("0".to_string(), value.clone())],
+            Some(Value::Indexed(values)) => {
+                values.iter().map(|(i, v)| (i.to_string(), v.clone())).collect()
+            }
+            Some(Value::Associative(values)) => {
+                values.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+            }
+        }
+    }
+
+    /// Performs an assignment word split by [`split_assignment`]: `name=value`, `name+=value`,
+    /// `name[subscript]=value` and compound `name=(...)` values.
+    pub fn assign(&mut self, target: &str, value: &AssignmentValue) -> Result<(), String> {
+        let (target, append) = match target.strip_suffix('+') {
+            Some(target) => (target, true),
+            None => (target, false),
+        };
+        let (name, subscript) = split_subscript(target);
+        let value = match value {
+            AssignmentValue::Compound(_) if subscript.is_some() => {
+                return Err(format!("{}: cannot assign list to array member", target));
+            }
+            AssignmentValue::Compound(elements) => return self.assign_compound(name, elements, append),
+            AssignmentValue::Scalar(value) => value,
+        };
+        match subscript {
+            Some(subscript) => self.set_element(name, subscript, value, append),
+            None if append => {
+                let current = self.get(name).unwrap_or_default();
+                let integer = self.values.get(name).is_some_and(|v| v.attributes.integer);
+                match integer {
+                    true => self.set(name, format!("{}+({})", current.trim(), value)),
+                    false => self.set(name, current + value),
+                }
+            }
+            None => self.set(name, value.to_string()),
+        }
+    }
+
+    pub fn unset_element(&mut self, name: &str, subscript: &str) -> Result<(), String> {
+        if self.values.get(name).is_some_and(|v| v.attributes.readonly) {
+            return Err(format!("{}: cannot unset: readonly variable", name));
+        }
+        let index = match self.values.get(name).and_then(|v| v.value.as_ref()) {
+            Some(Value::Indexed(values)) => self.resolve_index(name, values, subscript)?,
+            _ => None,
+        };
+        match self.values.get_mut(name).and_then(|v| v.value.as_mut()) {
+            Some(Value::Associative(values)) => {
+                values.remove(subscript);
+            }
+            Some(Value::Indexed(values)) => {
+                if let Some(index) = index {
+                    values.remove(&index);
+                }
+            }
+            Some(Value::Scalar(_)) if subscript == "0" => {
+                self.values.remove(name);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Assigns a scalar, applying the `-i`, `-l` and `-u` attributes. Assigning to an array
+    /// sets its element 0.
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), String> {
+        let value = self.transform(name, value)?;
+        let variable = self.writable(name)?;
+        match &mut variable.value {
+            Some(Value::Indexed(values)) => {
+                values.insert(0, value);
+            }
+            Some(Value::Associative(values)) => {
+                values.insert("0".to_string(), value);
+            }
+            _ => variable.value = Some(Value::Scalar(value)),
+        }
+        Ok(())
+    }
+
+    pub fn set_array(&mut self, name: &str, values: BTreeMap<usize, String>) -> Result<(), String> {
+        self.writable(name)?.value = Some(Value::Indexed(values));
+        Ok(())
+    }
+
+    pub fn get_array(&self, name: &str) -> BTreeMap<usize, String> {
+        match self.values.get(name).and_then(|v| v.value.as_ref()) {
+            Some(Value::Indexed(values)) => values.clone(),
+            Some(Value::Scalar(value)) => BTreeMap::from([(0, value.clone())]),
+            Some(Value::Associative(values)) => values.values().cloned().enumerate().collect(),
+            None => BTreeMap::new(),
+        }
+    }
+
+    /// Turns `name` into an empty array of the given kind unless it already is one.
+    pub fn declare_array(&mut self, name: &str, associative: bool) -> Result<(), String> {
+        let variable = self.writable(name)?;
+        match (&variable.value, associative) {
+            (Some(Value::Indexed(_)), false) | (Some(Value::Associative(_)), true) => (),
+            (Some(Value::Indexed(_)), true) | (Some(Value::Associative(_)), false) => {
+                return Err(format!("{}: cannot convert array", name));
+            }
+            (Some(Value::Scalar(value)), false) => {
+                variable.value = Some(Value::Indexed(BTreeMap::from([(0, value.clone())])));
+            }
+            (Some(Value::Scalar(value)), true) => {
+                let values = BTreeMap::from([("0".to_string(), value.clone())]);
+                variable.value = Some(Value::Associative(values));
+            }
+            (None, false) => variable.value = Some(Value::Indexed(BTreeMap::new())),
+            (None, true) => variable.value = Some(Value::Associative(BTreeMap::new())),
+        }
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<(), String> {
+        if self.values.get(name).is_some_and(|v| v.attributes.readonly) {
+            return Err(format!("{}: cannot unset: readonly variable", name));
+        }
+        self.values.remove(name);
+        Ok(())
+    }
+
+    /// The attributes of `name`, declaring it without a value if needed.
+    pub fn attributes_mut(&mut self, name: &str) -> &mut Attributes {
+        &mut self.values.entry(name.to_string()).or_default().attributes
+    }
+
+    /// Puts back a variable saved with [`Variables::get_variable`], bypassing `readonly`.
+    pub fn restore(&mut self, name: &str, saved: Option<Variable>) {
+        match saved {
+            Some(variable) => self.values.insert(name.to_string(), variable),
+            None => self.values.remove(name),
+        };
+    }
+
+    /// The environment for child processes: every exported variable with a scalar value.
+    pub fn exported(&self) -> Vec<(String, String)> {
+        let mut exported: Vec<(String, String)> = self
+            .values
+            .iter()
+            .filter(|(_, variable)| variable.attributes.exported)
+            .filter_map(|(name, variable)| match &variable.value {
+                Some(Value::Scalar(value)) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect();
+        exported.sort();
+        exported
+    }
+
+    pub fn names(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = self.values.keys().collect();
+        names.sort();
+        names
+    }
+
+    /// `name` as `declare -p` prints it, e.g. `declare -x HOME="/root"`.
+    pub fn declaration(&self, name: &str) -> Option<String> {
+        let variable = self.values.get(name)?;
+        let attributes = &variable.attributes;
+        let mut flags: String = [
+            (matches!(variable.value, Some(Value::Indexed(_))), 'a'),
+            (matches!(variable.value, Some(Value::Associative(_))), 'A'),
+            (attributes.integer, 'i'),
+            (attributes.lowercase, 'l'),
+            (attributes.readonly, 'r'),
+            (attributes.uppercase, 'u'),
+            (attributes.exported, 'x'),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| *flag)
+        .collect();
+        if flags.is_empty() {
+            flags.push('-');
+        }
+        let value = match &variable.value {
+            None => return Some(format!("declare -{} {}", flags, name)),
+            Some(Value::Scalar(value)) => quote(value),
+            Some(Value::Indexed(values)) => {
+                let elements: Vec<String> = values
+                    .iter()
+                    .map(|(index, value)| format!("[{}]={}", index, quote(value)))
+                    .collect();
+                format!("({})", elements.join(" "))
+            }
+            Some(Value::Associative(values)) => {
+                let elements: String = values
+                    .iter()
+                    .map(|(key, value)| format!("[{}]={} ", key, quote(value)))
+                    .collect();
+                format!("({})", elements)
+            }
+        };
+        Some(format!("declare -{} {}={}", flags, name, value))
+    }
+
+    fn set_element(&mut self, name: &str, subscript: &str, value: &str, append: bool) -> Result<(), String> {
+        let existing = self.get_element(name, subscript)?;
+        let value = match append {
+            true => existing.unwrap_or_default() + value,
+            false => value.to_string(),
+        };
+        let value = self.transform(name, value)?;
+        let index = match self.values.get(name).and_then(|v| v.value.as_ref()) {
+            Some(Value::Associative(_)) => None,
+            Some(Value::Indexed(values)) => self.resolve_index(name, values, subscript)?,
+            _ => self.resolve_index(name, &BTreeMap::new(), subscript)?,
+        };
+        let variable = self.writable(name)?;
+        match (&mut variable.value, index) {
+            (Some(Value::Associative(values)), _) => {
+                values.insert(subscript.to_string(), value);
+            }
+            (Some(Value::Indexed(values)), Some(index)) => {
+                values.insert(index, value);
+            }
+            (_, None) => return Err(format!("{}[{}]: bad array subscript", name, subscript)),
+            (current, Some(index)) => {
+                let mut values = BTreeMap::new();
+                if let Some(Value::Scalar(scalar)) = current.take() {
+                    values.insert(0, scalar);
+                }
+                values.insert(index, value);
+                *current = Some(Value::Indexed(values));
+            }
+        }
+        Ok(())
+    }
+
+    fn assign_compound(&mut self, name: &str, elements: &[String], append: bool) -> Result<(), String> {
+        let associative = matches!(
+            self.values.get(name).and_then(|v| v.value.as_ref()),
+            Some(Value::Associative(_))
+        );
+        if associative {
+            let mut values = match (append, self.values.get(name).and_then(|v| v.value.clone())) {
+                (true, Some(Value::Associative(values))) => values,
+                _ => BTreeMap::new(),
+            };
+            for element in elements {
+                let Some((key, value)) = split_element(element) else {
+                    return Err(format!("{}: {}: must use subscript when assigning associative array", name, element));
+                };
+                values.insert(key.to_string(), self.transform(name, value.to_string())?);
+            }
+            self.writable(name)?.value = Some(Value::Associative(values));
+            return Ok(());
+        }
+
+        let mut values = match append {
+            true => self.get_array(name),
+            false => BTreeMap::new(),
+        };
+        let mut next = values.keys().next_back().map_or(0, |last| last + 1);
+        for element in elements {
+            let value = match split_element(element) {
+                Some((subscript, value)) => {
+                    next = self
+                        .resolve_index(name, &values, subscript)?
+                        .ok_or_else(|| format!("{}[{}]: bad array subscript", name, subscript))?;
+                    value
+                }
+                None => element,
+            };
+            values.insert(next, self.transform(name, value.to_string())?);
+            next += 1;
+        }
+        self.writable(name)?.value = Some(Value::Indexed(values));
+        Ok(())
+    }
+
+    // `None` for negative indices that reach before the first element
+    fn resolve_index(
+        &self,
+        name: &str,
+        values: &BTreeMap<usize, String>,
+        subscript: &str,
+    ) -> Result<Option<usize>, String> {
+        let index = arithmetic::evaluate(subscript, &|name| self.get(name))
+            .map_err(|e| format!("{}: {}", name, e))?;
+        if index >= 0 {
+            return Ok(Some(index as usize));
+        }
+        let len = values.keys().next_back().map_or(0, |last| last + 1) as i64;
+        Ok(usize::try_from(len + index).ok())
+    }
+
+    /// How many times `name` has been assigned.
+    pub fn writes(&self, name: &str) -> u64 {
+        self.writes.get(name).copied().unwrap_or(0)
+    }
+
+    fn writable(&mut self, name: &str) -> Result<&mut Variable, String> {
+        let variable = self.values.entry(name.to_string()).or_default();
+        match variable.attributes.readonly {
+            true => Err(format!("{}: readonly variable", name)),
+            false => {
+                *self.writes.entry(name.to_string()).or_default() += 1;
+                Ok(variable)
+            }
+        }
+    }
+
+    fn transform(&self, name: &str, value: String) -> Result<String, String> {
+        let Some(attributes) = self.values.get(name).map(|v| &v.attributes) else {
+            return Ok(value);
+        };
+        if attributes.integer {
+            return arithmetic::evaluate(&value, &|name| self.get(name)).map(|n| n.to_string());
+        }
+        Ok(match (attributes.lowercase, attributes.uppercase) {
+            (true, _) => value.to_lowercase(),
+            (_, true) => value.to_uppercase(),
+            _ => value,
+        })
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// Splits an assignment word such as `FOO=bar`, `FOO+=bar` or `arr[3]=x` into its target
+/// (everything before the `=`) and value.
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let (target, value) = word.split_once('=')?;
+    let (name, _) = split_subscript(target.strip_suffix('+').unwrap_or(target));
+    (is_valid_name(name) && !name.contains('[')).then_some((target, value))
+}
+
+/// Splits `name[subscript]` into its parts.
+pub fn split_subscript(target: &str) -> (&str, Option<&str>) {
+    match target.strip_suffix(']').and_then(|t| t.split_once('[')) {
+        Some((name, subscript)) => (name, Some(subscript)),
+        None => (target, None),
+    }
+}
+
+// `[key]=value` inside a compound value
+fn split_element(element: &str) -> Option<(&str, &str)> {
+    let (subscript, value) = element.strip_prefix('[')?.split_once("]=")?;
+    Some((subscript, value))
+}
+
+// double-quotes `value` the way `declare -p` does
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
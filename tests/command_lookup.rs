@@ -0,0 +1,73 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use common::Sandbox;
+
+// a sandbox with empty `bin` and `bin2` directories ahead of the system ones on PATH, and a
+// `tool` script ready to be copied into them
+fn sandbox() -> (Sandbox, String) {
+    let sandbox = Sandbox::new();
+    for dir in ["bin", "bin2", "staged"] {
+        fs::create_dir(sandbox.path(dir)).unwrap();
+    }
+    sandbox.write("staged/tool", "#!/bin/sh\necho tool\n");
+    fs::set_permissions(sandbox.path("staged/tool"), fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(
+        "{}:{}:{}",
+        sandbox.path("bin").display(),
+        sandbox.path("bin2").display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    (sandbox, path)
+}
+
+#[test]
+fn finds_commands_installed_during_the_session() {
+    let (sandbox, path) = sandbox();
+    let output = sandbox.run_with("tool\ncp staged/tool bin/tool\ntool\n", &[("PATH", &path)]);
+    assert_eq!(output.stdout, "tool\n");
+    assert_eq!(output.stderr, "bash: tool: command not found\n");
+}
+
+#[test]
+fn command_not_found_goes_to_stderr() {
+    let output = common::run("nosuch 2>/dev/null\necho $?\nnosuch | wc -c\n");
+    assert_eq!(output.stdout.split_whitespace().collect::<Vec<_>>(), ["127", "0"]);
+    assert_eq!(output.stderr, "bash: nosuch: command not found\n");
+}
+
+#[test]
+fn drops_cached_paths_that_no_longer_exist() {
+    let (sandbox, path) = sandbox();
+    let script = "cp staged/tool bin2/tool\ntool\nhash -t tool\n\
+                  rm bin2/tool\ncp staged/tool bin/tool\ntool\nhash -t tool\n";
+    let output = sandbox.run_with(script, &[("PATH", &path)]);
+    let expected = format!(
+        "tool\n{}\ntool\n{}\n",
+        sandbox.path("bin2/tool").display(),
+        sandbox.path("bin/tool").display()
+    );
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn hash_builtin() {
+    let (sandbox, path) = sandbox();
+    let cp = std::env::split_paths(&path)
+        .map(|dir| dir.join("cp"))
+        .find(|cp| cp.is_file())
+        .unwrap();
+    let script = "hash\ncp staged/tool bin/tool\ntool\ntool\nhash\nhash -l\nhash -r\nhash\n\
+                  hash -d tool\n";
+    let output = sandbox.run_with(script, &[("PATH", &path)]);
+    let expected = format!(
+        "hash: hash table empty\ntool\ntool\nhits\tcommand\n   1\t{cp}\n   2\t{tool}\n\
+         builtin hash -p {cp} cp\nbuiltin hash -p {tool} tool\nhash: hash table empty\n",
+        cp = cp.display(),
+        tool = sandbox.path("bin/tool").display()
+    );
+    assert_eq!(output.stdout, expected);
+    assert_eq!(output.stderr, "hash: tool: not found\n");
+}
@@ -0,0 +1,103 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: i32,
+}
+
+/// A scratch directory that the shell runs in, with its own `HOME` and `HISTFILE`.
+pub struct Sandbox {
+    pub dir: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "shell-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        Sandbox { dir: dir.canonicalize().unwrap() }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub fn read(&self, name: &str) -> String {
+        fs::read_to_string(self.path(name)).unwrap()
+    }
+
+    pub fn write(&self, name: &str, contents: &str) {
+        fs::write(self.path(name), contents).unwrap();
+    }
+
+    /// Feeds `script` to the shell on standard input, the way a non-interactive shell reads
+    /// it, and collects what it wrote.
+    pub fn run(&self, script: &str) -> Output {
+        self.run_with(script, &[])
+    }
+
+    pub fn run_with(&self, script: &str, vars: &[(&str, &str)]) -> Output {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"));
+        command
+            .current_dir(&self.dir)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", &self.dir)
+            .env("HISTFILE", self.path(".history"))
+            .envs(vars.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = command.spawn().unwrap();
+        let pid = child.id() as libc::pid_t;
+        let mut stdin = child.stdin.take().unwrap();
+        let script = script.to_string();
+        thread::spawn(move || {
+            let _ = stdin.write_all(script.as_bytes());
+        });
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(child.wait_with_output());
+        });
+        let output = match receiver.recv_timeout(TIMEOUT) {
+            Ok(output) => output.unwrap(),
+            Err(_) => {
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+                panic!("the shell did not finish within {:?}", TIMEOUT);
+            }
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Output {
+            // the shell reports the end of its input like a Ctrl-D at the prompt
+            stdout: stdout.strip_suffix("CTRL-D\n").unwrap_or(&stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            status: output.status.code().unwrap_or(-1),
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn run(script: &str) -> Output {
+    Sandbox::new().run(script)
+}
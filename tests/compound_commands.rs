@@ -0,0 +1,65 @@
+mod common;
+
+use common::{run, Sandbox};
+
+#[test]
+fn while_read_loop_over_a_pipe() {
+    let output = run("printf 'a\\nb\\n' | while read line; do echo \"got $line\"; done\n");
+    assert_eq!(output.stdout, "got a\ngot b\n");
+}
+
+#[test]
+fn loops_over_several_lines() {
+    let script = "for x in 1 2 3; do\n  if [ $x = 2 ]; then continue; fi\n  echo $x\ndone\n\
+                  i=0; until [ $i = 1 ]; do echo i$i; i=1; done\n";
+    assert_eq!(run(script).stdout, "1\n3\ni0\n");
+}
+
+#[test]
+fn break_out_of_nested_loops() {
+    let script = "for x in a b; do for y in 1 2; do [ $y = 2 ] && break 2; echo $x$y; done; done\n\
+                  break\n";
+    let output = run(script);
+    assert_eq!(output.stdout, "a1\n");
+    assert_eq!(output.stderr, "break: only meaningful in a `for', `while', or `until' loop\n");
+}
+
+#[test]
+fn if_elif_else() {
+    let output = run("if false; then echo no; elif true; then echo elif; else echo else; fi\n");
+    assert_eq!(output.stdout, "elif\n");
+}
+
+#[test]
+fn and_or_lists_and_negation() {
+    let output = run("true && echo and || echo or\n! false && echo negated\nfalse || echo $?\n");
+    assert_eq!(output.stdout, "and\nnegated\n1\n");
+}
+
+#[test]
+fn groups_and_subshells() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("{ echo group; echo out; } > grouped\n(cd /; pwd)\npwd\n");
+    assert_eq!(output.stdout, format!("/\n{}\n", sandbox.dir.display()));
+    assert_eq!(sandbox.read("grouped"), "group\nout\n");
+}
+
+#[test]
+fn background_jobs_are_rejected() {
+    let output = run("echo a & echo b\necho c\n");
+    assert_eq!(output.stdout, "");
+    assert_eq!(output.stderr, "bash: `&': background jobs are not supported\n");
+    assert_eq!(output.status, 2);
+    let output = run("echo a &> /dev/null && echo b 2>&1\n");
+    assert_eq!(output.stdout, "b\n");
+}
+
+#[test]
+fn syntax_errors_end_a_script() {
+    let output = run("echo before\nfi\necho after\n");
+    assert_eq!(output.stdout, "before\n");
+    assert_eq!(output.stderr, "bash: syntax error near unexpected token `fi'\n");
+    assert_eq!(output.status, 2);
+    let output = run("while true; do\necho never\n");
+    assert_eq!(output.stderr, "bash: syntax error: unexpected end of file\n");
+}
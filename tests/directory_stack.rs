@@ -0,0 +1,18 @@
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn pushd_rejects_a_multibyte_option() {
+    let output = Sandbox::new().run("pushd -é\necho $?\n");
+    assert_eq!(output.stdout, "2\n");
+    assert!(output.stderr.starts_with("pushd: -é: invalid option\n"));
+}
+
+#[test]
+fn popd_rejects_a_multibyte_argument() {
+    let output = Sandbox::new().run("pushd / > /dev/null\npopd é\necho $?\npopd 1\n");
+    assert_eq!(output.stdout, "2\n");
+    assert!(output.stderr.starts_with("popd: é: invalid argument\n"));
+    assert!(output.stderr.contains("popd: 1: invalid argument\n"));
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn default_values() {
+    let output = run("d=\necho rm ${d:-/tmp/x}/y\necho ${u-def} ${d-def}.\n");
+    assert_eq!(output.stdout, "rm /tmp/x/y\ndef .\n");
+}
+
+#[test]
+fn assigned_defaults() {
+    let output = run("echo ${u:=new} $u\nd=\necho ${d=kept}. ${d:=set}\n");
+    assert_eq!(output.stdout, "new new\n. set\n");
+}
+
+#[test]
+fn alternate_values() {
+    let output = run("x=v\nd=\necho ${x:+alt} ${u:+alt}. ${d+set} ${d:+alt}.\n");
+    assert_eq!(output.stdout, "alt . set .\n");
+}
+
+#[test]
+fn errors_for_unset_values() {
+    let output = run("echo ${u:?missing}\necho after\n");
+    assert_eq!(output.stdout, "");
+    assert_eq!(output.stderr, "bash: u: missing\n");
+    assert_eq!(output.status, 1);
+    let output = run("echo ${u:?}\n");
+    assert_eq!(output.stderr, "bash: u: parameter null or not set\n");
+}
+
+#[test]
+fn array_slices_and_defaults() {
+    let output = run("a=(1 2 3)\necho ${a[@]:1:2}\necho ${a[@]:-none} ${e[@]:-none}\n");
+    assert_eq!(output.stdout, "2 3\n1 2 3 none\n");
+}
+
+#[test]
+fn unsupported_forms_are_bad_substitutions() {
+    let output = run("x=abc\necho ${x/a/b}\necho after\n");
+    assert_eq!(output.stdout, "");
+    assert_eq!(output.stderr, "bash: ${x/a/b}: bad substitution\n");
+    assert_eq!(output.status, 1);
+}
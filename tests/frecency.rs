@@ -0,0 +1,77 @@
+mod common;
+
+use std::fs;
+use std::thread;
+
+use common::Sandbox;
+
+fn sandbox() -> Sandbox {
+    let sandbox = Sandbox::new();
+    fs::create_dir_all(sandbox.path("a/project")).unwrap();
+    fs::create_dir_all(sandbox.path("b/project")).unwrap();
+    sandbox
+}
+
+#[test]
+fn jumps_to_the_highest_ranked_match() {
+    let sandbox = sandbox();
+    let script = "cd a/project\ncd ../..\ncd b/project\ncd ../..\ncd b/project\ncd /\n\
+                  z project\npwd\nz a proj\npwd\n";
+    let output = sandbox.run(script);
+    let expected = format!(
+        "{}\n{}\n",
+        sandbox.path("b/project").display(),
+        sandbox.path("a/project").display()
+    );
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn lists_candidates_best_last() {
+    let sandbox = sandbox();
+    let script = "cd a/project\ncd ../..\ncd b/project\ncd ../..\ncd b/project\nz -l proj\n";
+    let output = sandbox.run(script);
+    // each visit in the last hour counts four times
+    let expected = format!(
+        "4.0        {}\n8.0        {}\n",
+        sandbox.path("a/project").display(),
+        sandbox.path("b/project").display()
+    );
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn remembers_directories_across_sessions() {
+    let sandbox = sandbox();
+    sandbox.run("cd a/project\n");
+    let database = sandbox.read(".shell_frecency");
+    assert!(database.starts_with(&format!("{}|1|", sandbox.path("a/project").display())));
+    let output = sandbox.run("z proj\npwd\n");
+    assert_eq!(output.stdout, format!("{}\n", sandbox.path("a/project").display()));
+}
+
+#[test]
+fn forgets_a_directory() {
+    let sandbox = sandbox();
+    let output = sandbox.run("cd a/project\ncd ../..\nz -x a/project\nz project\nz -x a/project\n");
+    assert_eq!(output.stdout, "");
+    let expected = format!(
+        "z: no match for project\nz: {}: not in the database\n",
+        sandbox.path("a/project").display()
+    );
+    assert_eq!(output.stderr, expected);
+}
+
+#[test]
+fn concurrent_shells_keep_every_visit() {
+    let sandbox = sandbox();
+    let script = "cd a/project\ncd ../..\n".repeat(50);
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| sandbox.run(&script));
+        }
+    });
+    let database = sandbox.read(".shell_frecency");
+    let ranks: Vec<&str> = database.lines().filter_map(|line| line.split('|').nth(1)).collect();
+    assert_eq!(ranks, ["200", "200"]);
+}
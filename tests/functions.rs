@@ -0,0 +1,64 @@
+mod common;
+
+use common::{run, Sandbox};
+
+#[test]
+fn define_and_call() {
+    let output = run("greet() { echo \"hi $1 $#\"; }\ngreet bob x\nfunction k { echo k; }; k\n");
+    assert_eq!(output.stdout, "hi bob 2\nk\n");
+}
+
+#[test]
+fn return_status() {
+    let script = "f() { return 3; }\nf; echo $?\n\
+                  loop() { for i in 1 2 3; do [ $i = 2 ] && return 7; echo $i; done; }\n\
+                  loop; echo $?\nreturn 1\n";
+    let output = run(script);
+    assert_eq!(output.stdout, "3\n1\n7\n");
+    assert_eq!(
+        output.stderr,
+        "return: can only `return' from a function or sourced script\n"
+    );
+}
+
+#[test]
+fn local_variables_are_restored() {
+    let output = run("x=outer; g() { local x=inner; echo $x; }; g; echo $x\n");
+    assert_eq!(output.stdout, "inner\nouter\n");
+}
+
+#[test]
+fn return_trap() {
+    let script = "h() { trap 'echo returning' RETURN; echo body; }; h\n\
+                  trap 'echo outer' RETURN; g() { echo g; }; g\n";
+    assert_eq!(run(script).stdout, "body\nreturning\ng\n");
+}
+
+#[test]
+fn subshell_body() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("s() ( cd /; pwd ); s; pwd\n");
+    assert_eq!(output.stdout, format!("/\n{}\n", sandbox.dir.display()));
+}
+
+#[test]
+fn type_and_unset() {
+    let output = run("f() { return 3; }\ntype f\ntype -t f\nunset -f f; f\n");
+    assert_eq!(output.stdout, "f is a function\nf() { return 3; }\nfunction\n");
+    assert_eq!(output.stderr, "bash: f: command not found\n");
+}
+
+#[test]
+fn type_f_skips_functions() {
+    let script = "pwd() { :; }\ntype -f pwd\ntype -ft pwd\nf() { :; }\ntype -ft f || type -t f\n";
+    let output = run(script);
+    assert_eq!(output.stdout, "pwd is a shell builtin\nbuiltin\nfunction\n");
+}
+
+#[test]
+fn funcname_lists_the_call_stack() {
+    let script = "inner() { echo ${FUNCNAME[0]} ${FUNCNAME[1]} ${#FUNCNAME[@]}; }\n\
+                  outer() { inner; echo ${FUNCNAME[@]} ${#FUNCNAME[@]}; }\n\
+                  outer\necho [${FUNCNAME[@]}] ${#FUNCNAME[@]}\n";
+    assert_eq!(run(script).stdout, "inner outer 2\nouter 1\n[] 0\n");
+}
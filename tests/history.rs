@@ -0,0 +1,44 @@
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn histcontrol_ignoreboth_and_histignore() {
+    let script = "echo a\necho a\n echo secret\necho b\nls >/dev/null\ncat /dev/null\nhistory\n";
+    let vars = [("HISTCONTROL", "ignoreboth"), ("HISTIGNORE", "ls*:cat *")];
+    let output = Sandbox::new().run_with(script, &vars);
+    let expected = "a\na\nsecret\nb\n    1  echo a\n    2  echo b\n    3  history\n";
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn histcontrol_erasedups() {
+    let output = Sandbox::new().run_with(
+        "echo a\necho b\necho a\nhistory\n",
+        &[("HISTCONTROL", "erasedups")],
+    );
+    assert_eq!(output.stdout, "a\nb\na\n    1  echo b\n    2  echo a\n    3  history\n");
+}
+
+#[test]
+fn histsize_caps_the_history_list() {
+    let script = "echo 1\necho 2\necho 3\nhistory\n";
+    let output = Sandbox::new().run_with(script, &[("HISTSIZE", "2")]);
+    assert_eq!(output.stdout, "1\n2\n3\n    1  echo 3\n    2  history\n");
+}
+
+#[test]
+fn histfilesize_truncates_the_history_file() {
+    let sandbox = Sandbox::new();
+    sandbox.run_with("echo 1\necho 2\necho 3\nexit\n", &[("HISTFILESIZE", "2")]);
+    assert_eq!(sandbox.read(".history"), "echo 3\nexit\n");
+    sandbox.run_with("echo 4\nexit\n", &[("HISTFILESIZE", "3")]);
+    assert_eq!(sandbox.read(".history"), "exit\necho 4\nexit\n");
+}
+
+#[test]
+fn no_history_expansion_without_a_terminal() {
+    let output = Sandbox::new().run("echo one\necho two!!\necho ${!x}\n");
+    assert_eq!(output.stdout, "one\ntwo!!\n\n");
+    assert_eq!(output.stderr, "");
+}
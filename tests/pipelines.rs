@@ -0,0 +1,24 @@
+mod common;
+
+use common::{run, Sandbox};
+
+#[test]
+fn builtin_output_larger_than_a_pipe_buffer() {
+    let word = "x".repeat(200_000);
+    let output = run(&format!("echo {} | wc -c\n", word));
+    assert_eq!(output.stdout.trim(), "200001");
+}
+
+#[test]
+fn builtin_in_the_middle_of_a_pipeline() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo ignored | pwd | cat\n");
+    assert_eq!(output.stdout, format!("{}\n", sandbox.dir.display()));
+}
+
+#[test]
+fn builtin_stage_runs_in_a_subshell() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("cd / | cat\npwd\n");
+    assert_eq!(output.stdout, format!("{}\n", sandbox.dir.display()));
+}
@@ -0,0 +1,21 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn read_leaves_the_rest_of_stdin_to_the_shell() {
+    let output = run("read line\nfirst\necho got $line\nread -r a b\none two three\necho $b\n");
+    assert_eq!(output.stdout, "got first\ntwo three\n");
+}
+
+#[test]
+fn read_with_a_timeout_sees_pending_input() {
+    let output = run("read -t 1 x\nvalue\necho got $x\n");
+    assert_eq!(output.stdout, "got value\n");
+}
+
+#[test]
+fn mapfile_reads_only_the_lines_it_needs() {
+    let output = run("mapfile -n 2 lines\na\nb\necho ${lines[@]}\n");
+    assert_eq!(output.stdout, "a b\n");
+}
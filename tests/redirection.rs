@@ -0,0 +1,137 @@
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn duplicate_after_redirecting_stdout() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("ls nosuch > out 2>&1\n");
+    assert_eq!(output.stderr, "");
+    assert!(sandbox.read("out").contains("nosuch"));
+}
+
+#[test]
+fn duplicate_before_redirecting_stdout() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("ls nosuch 2>&1 > out\n");
+    assert_eq!(output.stderr, "");
+    assert!(output.stdout.contains("nosuch"));
+    assert_eq!(sandbox.read("out"), "");
+}
+
+#[test]
+fn builtin_output_to_stderr() {
+    let output = Sandbox::new().run("echo err >&2\necho out\n");
+    assert_eq!(output.stdout, "out\n");
+    assert_eq!(output.stderr, "err\n");
+}
+
+#[test]
+fn both_streams_to_a_file() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("ls nosuch &> both\necho more &>> both\n");
+    assert_eq!(output.stdout, "");
+    assert_eq!(output.stderr, "");
+    let both = sandbox.read("both");
+    assert!(both.contains("nosuch"));
+    assert!(both.ends_with("\nmore\n"));
+}
+
+#[test]
+fn closed_descriptors() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo hi >&-\ncat 0<&-\n");
+    assert_eq!(output.stdout, "");
+    assert!(output.stderr.contains("Bad file descriptor"));
+}
+
+#[test]
+fn noclobber_refuses_to_truncate_an_existing_file() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo one > f\nset -C\necho two > f\necho new > g\n");
+    assert!(output.stderr.contains("f: cannot overwrite existing file"));
+    assert_eq!(sandbox.read("f"), "one\n");
+    assert_eq!(sandbox.read("g"), "new\n");
+}
+
+#[test]
+fn noclobber_allows_appending_and_forcing() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo one > f\nset -o noclobber\necho two >| f\necho three >> f\n");
+    assert_eq!(output.stderr, "");
+    assert_eq!(sandbox.read("f"), "two\nthree\n");
+}
+
+#[test]
+fn noclobber_allows_devices_and_can_be_turned_off() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo one > f\nset -C\necho x > /dev/null\nset +C\necho two > f\n");
+    assert_eq!(output.stderr, "");
+    assert_eq!(sandbox.read("f"), "two\n");
+}
+
+#[test]
+fn quoted_operators_are_plain_text() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo \"<html>\"\nprintf '<%s>\\n' x\necho '>' x\necho \"2>&1\" y\n");
+    assert_eq!(output.stdout, "<html>\n<x>\n> x\n2>&1 y\n");
+    assert_eq!(output.stderr, "");
+    assert!(!sandbox.path("x").exists());
+}
+
+#[test]
+fn escaped_operators_are_plain_text() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo \\>y\necho \\<\\<EOF\n");
+    assert_eq!(output.stdout, ">y\n<<EOF\n");
+    assert!(!sandbox.path("y").exists());
+}
+
+#[test]
+fn quoted_targets_and_fds() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("f='a file'\necho one >\"$f\"\necho 2\">\"x\necho three 2>'err'\n");
+    assert_eq!(output.stdout, "2>x\nthree\n");
+    assert_eq!(sandbox.read("a file"), "one\n");
+    assert_eq!(sandbox.read("err"), "");
+    assert!(!sandbox.path("x").exists());
+}
+
+#[test]
+fn operators_joined_to_words() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo hi>f1\necho more>>f1\ncat<f1\nx=v\necho $x>f2\n");
+    assert_eq!(output.stdout, "hi\nmore\n");
+    assert_eq!(sandbox.read("f1"), "hi\nmore\n");
+    assert_eq!(sandbox.read("f2"), "v\n");
+}
+
+#[test]
+fn joined_operators_apply_in_order() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("ls nosuch>out 2>&1\nls nosuch 2>&1>out2\n");
+    assert!(sandbox.read("out").contains("nosuch"));
+    assert!(output.stdout.contains("nosuch"));
+    assert_eq!(sandbox.read("out2"), "");
+    assert_eq!(output.stderr, "");
+}
+
+#[test]
+fn joined_duplicates_and_closes() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo err>&2\necho both&>f\ncat 0<&-\necho 2&>g\n");
+    assert_eq!(output.stdout, "");
+    assert!(output.stderr.starts_with("err\n"));
+    assert!(output.stderr.contains("Bad file descriptor"));
+    assert_eq!(sandbox.read("f"), "both\n");
+    assert_eq!(sandbox.read("g"), "2\n");
+}
+
+#[test]
+fn joined_operators_under_noclobber() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run("echo one>f\nset -C\necho two>f\necho three>|g\necho four>>f\n");
+    assert!(output.stderr.contains("f: cannot overwrite existing file"));
+    assert_eq!(sandbox.read("f"), "one\nfour\n");
+    assert_eq!(sandbox.read("g"), "three\n");
+}